@@ -0,0 +1,111 @@
+/// Timing of individual pipeline stages
+///
+use std::time::{Duration, Instant};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::gtfs::{
+    Agency, Attribution, Calendar, CalendarDate, FareAttribute, FareRule, FeedInfo, Frequency,
+    GtfsError, GtfsFile, GtfsStore, Level, PathWay, Route, Shape, Stop, StopTime, TableFacory,
+    TicketingDeepLink, TicketingIdentifier, Transfer, Translation, Trip,
+};
+
+pub struct StageTiming {
+    pub stage: String,
+    pub items: usize,
+    pub duration: Duration,
+}
+
+fn time_table<I, S, F>(store: &mut S) -> Result<Option<StageTiming>, GtfsError>
+where
+    I: Serialize + DeserializeOwned + GtfsFile + 'static,
+    S: GtfsStore,
+    F: TableFacory,
+{
+    let started = Instant::now();
+    let Some(table) = store.try_decompress::<I, F>()? else {
+        return Ok(None);
+    };
+
+    Ok(Some(StageTiming {
+        stage: format!("parse {}", I::get_file_type().file_name()),
+        items: table.length(),
+        duration: started.elapsed(),
+    }))
+}
+
+/// Time parsing of every table present in the store
+pub fn bench_parse<S: GtfsStore, F: TableFacory>(
+    store: &mut S,
+) -> Result<Vec<StageTiming>, GtfsError> {
+    let timings = [
+        time_table::<Agency, S, F>(store)?,
+        time_table::<Stop, S, F>(store)?,
+        time_table::<Route, S, F>(store)?,
+        time_table::<Trip, S, F>(store)?,
+        time_table::<StopTime, S, F>(store)?,
+        time_table::<Calendar, S, F>(store)?,
+        time_table::<CalendarDate, S, F>(store)?,
+        time_table::<FareAttribute, S, F>(store)?,
+        time_table::<FareRule, S, F>(store)?,
+        time_table::<Shape, S, F>(store)?,
+        time_table::<Frequency, S, F>(store)?,
+        time_table::<Transfer, S, F>(store)?,
+        time_table::<PathWay, S, F>(store)?,
+        time_table::<Level, S, F>(store)?,
+        time_table::<FeedInfo, S, F>(store)?,
+        time_table::<Translation, S, F>(store)?,
+        time_table::<Attribution, S, F>(store)?,
+        time_table::<TicketingIdentifier, S, F>(store)?,
+        time_table::<TicketingDeepLink, S, F>(store)?,
+    ];
+    Ok(timings.into_iter().flatten().collect())
+}
+
+/// Keep the fastest run of every stage
+pub fn best_of(runs: Vec<Vec<StageTiming>>) -> Vec<StageTiming> {
+    let mut best: Vec<StageTiming> = Vec::new();
+
+    for run in runs {
+        for timing in run {
+            match best.iter_mut().find(|x| x.stage == timing.stage) {
+                Some(existing) if existing.duration > timing.duration => *existing = timing,
+                Some(_) => (),
+                None => best.push(timing),
+            }
+        }
+    }
+
+    best
+}
+
+/// Print stage breakdown with share of total time
+pub fn print_timings(timings: &[StageTiming]) {
+    let total: Duration = timings.iter().map(|x| x.duration).sum();
+
+    println!(
+        "{:<32} {:>12} {:>12} {:>14} {:>7}",
+        "stage", "items", "time", "items/sec", "share"
+    );
+
+    for timing in timings {
+        let seconds = timing.duration.as_secs_f64();
+        let rate = if seconds > 0.0 {
+            timing.items as f64 / seconds
+        } else {
+            0.0
+        };
+        let share = if total.is_zero() {
+            0.0
+        } else {
+            seconds / total.as_secs_f64() * 100.0
+        };
+
+        println!(
+            "{:<32} {:>12} {:>12.2?} {:>14.0} {:>6.1}%",
+            timing.stage, timing.items, timing.duration, rate, share
+        );
+    }
+
+    println!("{:<32} {:>12} {:>12.2?}", "total", "", total);
+}
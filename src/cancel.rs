@@ -0,0 +1,65 @@
+/// Cooperative cancellation on ctrl-c, long loops check the flag and stop early
+///
+/// The first SIGINT or SIGTERM only sets the flag so completed work can be flushed,
+/// a second one terminates the process right away.
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Granularity of waits interrupted by cancellation
+const SLEEP_STEP: Duration = Duration::from_millis(100);
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Cancelled")]
+pub struct Cancelled;
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+    // Only async-signal-safe calls are allowed here
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+/// Cancel on SIGINT and SIGTERM instead of terminating
+pub fn install_handler() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Error if the run was cancelled, called between units of work
+pub fn check() -> Result<(), Cancelled> {
+    match is_cancelled() {
+        true => Err(Cancelled),
+        false => Ok(()),
+    }
+}
+
+/// Sleep unless cancelled in the meantime, error if cancelled
+pub fn sleep(duration: Duration) -> Result<(), Cancelled> {
+    let deadline = Instant::now() + duration;
+    loop {
+        check()?;
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        std::thread::sleep(left.min(SLEEP_STEP));
+    }
+}
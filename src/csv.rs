@@ -1,6 +1,5 @@
 use std::{
     any::type_name,
-    collections::HashMap,
     error,
     fs::{File, OpenOptions},
     io::{self, BufRead, BufReader, BufWriter, Read, Write},
@@ -18,6 +17,8 @@ use row::{parse_csv_line, serialize_to_csv, to_csv_row};
 
 use rowread::deserialize_item;
 
+use crate::hashing::FastHashMap;
+
 use self::row::{FieldReference, FieldReferenceCollection};
 pub mod header;
 pub mod row;
@@ -56,8 +57,8 @@ where
     }
 }
 
-pub struct CsvTableWriter<S: Serialize> {
-    writer: BufWriter<File>,
+pub struct CsvTableWriter<S: Serialize, W: Write = BufWriter<File>> {
+    writer: W,
     _phantom: PhantomData<S>,
     headers: Option<Vec<String>>,
 }
@@ -88,60 +89,108 @@ impl<S: Serialize> CsvTableWriter<S> {
             _phantom: PhantomData,
         }
     }
+}
+
+impl<S: Serialize, W: Write> CsvTableWriter<S, W> {
+    /// Write a new table into writer, the header is written with the first row
+    pub fn from_writer(writer: W) -> Self {
+        CsvTableWriter {
+            writer,
+            headers: None,
+            _phantom: PhantomData,
+        }
+    }
 
     /// Write header to file and set internal header storage
-    fn write_header(&mut self, headers: Vec<String>) -> &Vec<String> {
-        self.writer.write(to_csv_row(&headers).as_bytes()).unwrap();
-        self.writer.write("\n".as_bytes()).unwrap();
+    fn write_header(&mut self, headers: Vec<String>) -> io::Result<&Vec<String>> {
+        self.writer.write_all(to_csv_row(&headers).as_bytes())?;
+        self.writer.write_all("\n".as_bytes())?;
         self.headers = Some(headers);
-        self.headers.as_ref().unwrap()
+        Ok(self.headers.as_ref().unwrap())
     }
 
     /// Writes row to the end of the file
-    pub fn write_row(&mut self, item: &S) {
+    pub fn write_row(&mut self, item: &S) -> io::Result<()> {
         let headers = match &self.headers {
             Some(value) => value,
 
-            None => self.write_header(get_columns(&item).iter().map(|x| x.to_string()).collect()),
+            None => {
+                self.write_header(get_columns(&item).iter().map(|x| x.to_string()).collect())?
+            }
         };
 
         let serialized = serialize_to_csv(headers, item);
 
-        self.writer.write(serialized.as_bytes()).unwrap();
-        self.writer.write("\n".as_bytes()).unwrap();
+        self.writer.write_all(serialized.as_bytes())?;
+        self.writer.write_all("\n".as_bytes())
+    }
+
+    /// Flush and return the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
     }
 }
 
+/// Serialize records to csv text including the header, None if there are no records
+pub fn to_csv_text<S: Serialize>(items: &[S]) -> Option<String> {
+    let first = items.first()?;
+    let headers = get_columns(first);
+
+    let mut text = to_csv_row(&headers);
+    text.push('\n');
+
+    for item in items {
+        text.push_str(&serialize_to_csv(&headers, item));
+        text.push('\n');
+    }
+
+    Some(text)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CsvError {
+    #[error("Could not read csv")]
+    Io(#[from] io::Error),
+    /// Row with a missing column or a value of the wrong type
+    #[error("Could not deserialize {type_name}: {error}")]
+    Row {
+        type_name: &'static str,
+        error: rowread::Error,
+    },
+}
+
 pub struct CsvTableReader<R: Read> {
     reader: R,
-    headers: HashMap<String, usize>,
+    headers: FastHashMap<String, usize>,
 }
 
-pub fn from_file<'a, P: AsRef<Path>>(path: P) -> CsvTableReader<BufReader<File>> {
-    let file = OpenOptions::new().read(true).open(path).unwrap();
+pub fn from_file<'a, P: AsRef<Path>>(path: P) -> Result<CsvTableReader<BufReader<File>>, CsvError> {
+    let file = OpenOptions::new().read(true).open(path)?;
     let reader = BufReader::new(file);
     CsvTableReader::new(reader)
 }
 
 impl<R: Read + BufRead> CsvTableReader<R> {
-    pub fn new(mut reader: R) -> Self {
+    /// Reader positioned after the header line
+    pub fn new(mut reader: R) -> Result<Self, CsvError> {
         // File already has some data inside, get the headers
         // let mut first_line = String::new();
 
         let mut line_buf = String::new();
         let mut field_buf = Vec::new();
 
-        reader.read_line(&mut line_buf).unwrap();
+        reader.read_line(&mut line_buf)?;
 
         parse_csv_line(line_buf.as_str(), &mut field_buf);
 
-        let mut headers = HashMap::new();
+        let mut headers = FastHashMap::default();
 
         for (col_i, col) in field_buf.into_str_vec(&line_buf).iter().enumerate() {
             headers.insert(col.to_string(), col_i);
         }
 
-        CsvTableReader { reader, headers }
+        Ok(CsvTableReader { reader, headers })
     }
 
     /// Deserialize one using buffer as intermediate storage
@@ -149,12 +198,12 @@ impl<R: Read + BufRead> CsvTableReader<R> {
         &mut self,
         field_buf: &'de mut Vec<FieldReference>,
         line_buf: &'de mut String,
-    ) -> Result<Option<D>>
+    ) -> Result<Option<D>, CsvError>
     where
         D: Deserialize<'de>,
     {
         line_buf.clear();
-        let num_read = self.reader.read_line(line_buf).unwrap();
+        let num_read = self.reader.read_line(line_buf)?;
 
         if num_read == 0 {
             return Ok(None);
@@ -162,9 +211,38 @@ impl<R: Read + BufRead> CsvTableReader<R> {
 
         parse_csv_line(&line_buf, field_buf);
 
-        let deserialized = deserialize_item::<D>(&self.headers, field_buf, line_buf)
-            .with_context(|| format!("Could not deserialize {}", type_name::<D>()))?;
+        let deserialized =
+            deserialize_item::<D>(&self.headers, field_buf, line_buf).map_err(|error| {
+                CsvError::Row {
+                    type_name: type_name::<D>(),
+                    error,
+                }
+            })?;
 
         Ok(Some(deserialized))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Row {
+        a: u32,
+        b: String,
+    }
+
+    #[test]
+    fn test_unreadable_header() {
+        let result = CsvTableReader::new(Cursor::new(vec![0xff, 0xfe, b'\n']));
+        assert!(matches!(result, Err(CsvError::Io(_))));
+
+        let mut reader = CsvTableReader::new(Cursor::new("a,b\n1,2\n")).unwrap();
+        let (mut field_buf, mut line_buf) = (Vec::new(), String::new());
+        let row: Row = reader.read(&mut field_buf, &mut line_buf).unwrap().unwrap();
+        assert_eq!(row.a, 1);
+    }
+}
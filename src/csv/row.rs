@@ -7,16 +7,17 @@ use serde::{
     Serialize,
 };
 use std::{
-    collections::HashMap,
     error,
     fmt::{self},
 };
 
 use serde::{de, ser};
 
+use crate::hashing::FastHashMap;
+
 struct RowSerializer<'a, H: AsRef<str>> {
     headers: &'a [H],
-    current_item: HashMap<&'static str, String>,
+    current_item: FastHashMap<&'static str, String>,
 }
 
 #[derive(Debug)]
@@ -556,7 +557,7 @@ pub fn serialize_to_columns<S: Serialize, H: AsRef<str>>(headers: &[H], value: S
 pub fn serialize_to_csv<S: Serialize, H: AsRef<str>>(headers: &[H], value: S) -> String {
     let mut my_serializer = RowSerializer {
         headers: headers,
-        current_item: HashMap::new(),
+        current_item: FastHashMap::default(),
     };
 
     value.serialize(&mut my_serializer).unwrap()
@@ -1,5 +1,4 @@
 use std::any::type_name;
-use std::collections::HashMap;
 use std::error;
 use std::fmt;
 use std::ops::{AddAssign, MulAssign, Neg};
@@ -11,6 +10,7 @@ use serde::de::{
 use serde::Deserialize;
 
 use super::row::FieldReference;
+use crate::hashing::FastHashMap;
 
 #[derive(Debug)]
 pub enum Error {
@@ -403,7 +403,7 @@ impl<'a, 'b, 'de> MapAccess<'de> for RecordVisitor<'a, 'b, 'de> {
 /// Lifetime 'de is for the data that is beinf deserialized
 /// Lifetime 'a is for reference to parent element
 struct CsvRow<'a, 'de> {
-    header: &'a HashMap<String, usize>,
+    header: &'a FastHashMap<String, usize>,
     divisions: &'a Vec<FieldReference>,
     data: &'de str,
 }
@@ -423,7 +423,7 @@ impl<'a, 'de> CsvRow<'a, 'de> {
 }
 
 pub fn deserialize_item<'a, 'de, D: Deserialize<'de>>(
-    header: &'a HashMap<String, usize>,
+    header: &'a FastHashMap<String, usize>,
     record: &'a Vec<FieldReference>,
     data: &'de str,
 ) -> Result<D, Error> {
@@ -1,62 +1,393 @@
-use std::{marker::PhantomData, slice::Iter};
+/// Append-only tables stored on disk, read back by index or in order
+///
+/// Records are json encoded one after another into a data file. A fixed-size
+/// index file holds offset and length of every record, so reading any record
+/// takes one seek in each file and tables may be far larger than memory.
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+use anyhow::{bail, Context, Result};
+use indicatif::HumanBytes;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::gtfs::Pushable;
+
+/// Offset and length of a record, both u64
+const INDEX_ENTRY_SIZE: u64 = 16;
+
+/// Distinguishes temporary tables of one process
+static TEMPORARY_TABLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Directory of temporary tables used when --spill-dir is not given
+pub const SPILL_DIR_ENV: &str = "RDTFS_SPILL_DIR";
+
+/// Free space left on the spill device when no reserve is configured
+const DEFAULT_SPILL_RESERVE: u64 = 256 * 1024 * 1024;
+
+/// Bytes written to a temporary table between checks of free space
+const SPACE_CHECK_INTERVAL: u64 = 64 * 1024 * 1024;
+
+static SPILL_DIR: OnceLock<PathBuf> = OnceLock::new();
+static SPILL_RESERVE: OnceLock<u64> = OnceLock::new();
+
+/// Put temporary tables into dir instead of the system temp dir
+pub fn set_spill_dir<P: AsRef<Path>>(dir: P) -> Result<()> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        bail!("Spill directory {dir:?} does not exist");
+    }
+    let _ = SPILL_DIR.set(dir.to_path_buf());
+    Ok(())
+}
+
+/// Fail writes to temporary tables once less than bytes are free
+pub fn set_spill_reserve(bytes: u64) {
+    let _ = SPILL_RESERVE.set(bytes);
+}
+
+pub fn spill_dir() -> PathBuf {
+    SPILL_DIR.get().cloned().unwrap_or_else(std::env::temp_dir)
+}
+
+fn spill_reserve() -> u64 {
+    SPILL_RESERVE
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_SPILL_RESERVE)
+}
+
+/// Bytes available to unprivileged users on the filesystem of path, none on other platforms
+pub fn available_space(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let stat = unsafe { stat.assume_init() };
+        // Field widths differ between platforms
+        #[allow(clippy::unnecessary_cast)]
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// Error if writing to dir would eat into the reserve
+fn ensure_space(dir: &Path, reserve: u64) -> Result<()> {
+    let Some(available) = available_space(dir) else {
+        return Ok(());
+    };
+    if available < reserve {
+        bail!(
+            "Only {} free in {dir:?}, less than the reserve of {}, \
+            set --spill-dir or {SPILL_DIR_ENV} to a larger location",
+            HumanBytes(available),
+            HumanBytes(reserve)
+        );
+    }
+    Ok(())
+}
 
 pub struct Table<Item> {
-    data: Vec<Item>,
+    data_path: PathBuf,
+    index_path: PathBuf,
+    data: BufWriter<File>,
+    index: BufWriter<File>,
+    /// Opened on the first random read
+    readers: Option<(File, File)>,
+    data_len: u64,
+    len: usize,
+    /// Remove files on drop
+    temporary: bool,
+    /// Data length at which free space is checked again, temporary tables only
+    next_space_check: Option<u64>,
+    /// First failed push through Pushable, later pushes are dropped
+    push_error: Option<anyhow::Error>,
+    _phantom: PhantomData<Item>,
 }
 
-/// Stores items in a table
-impl<Item> Table<Item> {
-    pub fn new() -> Self {
-        Table { data: Vec::new() }
+impl<Item: Serialize + DeserializeOwned> Table<Item> {
+    /// Empty table with records stored at path and the index next to it
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data_path = path.as_ref().to_path_buf();
+        let mut index_name = data_path
+            .file_name()
+            .context("Table path has no file name")?
+            .to_owned();
+        index_name.push(".idx");
+        let index_path = data_path.with_file_name(index_name);
+
+        let create =
+            |path: &Path| File::create(path).with_context(|| format!("Could not create {path:?}"));
+
+        Ok(Table {
+            data: BufWriter::new(create(&data_path)?),
+            index: BufWriter::new(create(&index_path)?),
+            data_path,
+            index_path,
+            readers: None,
+            data_len: 0,
+            len: 0,
+            temporary: false,
+            next_space_check: None,
+            push_error: None,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Empty table in the spill dir, removed when dropped
+    pub fn temporary() -> Result<Self> {
+        Self::temporary_in(&spill_dir())
+    }
+
+    /// Empty table in dir, removed when dropped
+    pub fn temporary_in(dir: &Path) -> Result<Self> {
+        ensure_space(dir, spill_reserve())?;
+        let id = TEMPORARY_TABLES.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("rdtfs-table-{}-{id}", std::process::id()));
+        let mut table = Self::create(path)?;
+        table.temporary = true;
+        table.next_space_check = Some(SPACE_CHECK_INTERVAL);
+        Ok(table)
     }
 
-    /// Add item to store
-    pub fn append(&mut self, item: Item) {
-        self.data.push(item)
+    pub fn push(&mut self, item: &Item) -> Result<()> {
+        if let Some(next) = self.next_space_check {
+            if self.data_len >= next {
+                let dir = self.data_path.parent().unwrap_or(Path::new("."));
+                ensure_space(dir, spill_reserve())?;
+                self.next_space_check = Some(self.data_len + SPACE_CHECK_INTERVAL);
+            }
+        }
+        let record = serde_json::to_vec(item)?;
+        self.data.write_all(&record)?;
+        self.index.write_all(&self.data_len.to_le_bytes())?;
+        self.index.write_all(&(record.len() as u64).to_le_bytes())?;
+
+        self.data_len += record.len() as u64;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Error of a push through Pushable, records pushed after it are missing
+    pub fn push_error(&self) -> Option<&anyhow::Error> {
+        self.push_error.as_ref()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.data.flush()?;
+        self.index.flush()?;
+        Ok(())
+    }
+
+    /// Record at index, None if the table is shorter
+    pub fn get(&mut self, index: usize) -> Result<Option<Item>> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        self.flush()?;
+
+        if self.readers.is_none() {
+            self.readers = Some((File::open(&self.data_path)?, File::open(&self.index_path)?));
+        }
+        let (data, index_file) = self.readers.as_mut().unwrap();
+
+        let mut entry = [0u8; INDEX_ENTRY_SIZE as usize];
+        index_file.seek(SeekFrom::Start(index as u64 * INDEX_ENTRY_SIZE))?;
+        index_file.read_exact(&mut entry)?;
+        let (offset, length) = parse_entry(&entry);
+
+        let mut record = vec![0u8; length as usize];
+        data.seek(SeekFrom::Start(offset))?;
+        data.read_exact(&mut record)?;
+
+        let item = serde_json::from_slice(&record)
+            .with_context(|| format!("Corrupt record {index} in {:?}", self.data_path))?;
+        Ok(Some(item))
+    }
+
+    /// All records in the order they were pushed
+    pub fn iter(&mut self) -> Result<TableIterator<Item>> {
+        self.flush()?;
+        Ok(TableIterator {
+            data: BufReader::new(File::open(&self.data_path)?),
+            index: BufReader::new(File::open(&self.index_path)?),
+            remaining: self.len,
+            buf: Vec::new(),
+            _phantom: PhantomData,
+        })
     }
 }
 
-/// Iterator implementation
-pub struct TableIterator<'a, Item> {
-    iter: Iter<'a, Item>,
+impl<Item> Drop for Table<Item> {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_file(&self.data_path);
+            let _ = fs::remove_file(&self.index_path);
+        }
+    }
+}
 
+impl<Item: Serialize + DeserializeOwned> Pushable<Item> for Table<Item> {
+    fn push(&mut self, item: Item) {
+        if self.push_error.is_some() {
+            return;
+        }
+        if let Err(err) = Table::push(self, &item) {
+            self.push_error =
+                Some(err.context(format!("Could not store record in {:?}", self.data_path)));
+        }
+    }
+
+    fn length(&self) -> usize {
+        self.len
+    }
+
+    fn push_error(&self) -> Option<&anyhow::Error> {
+        Table::push_error(self)
+    }
+}
+
+fn parse_entry(entry: &[u8; INDEX_ENTRY_SIZE as usize]) -> (u64, u64) {
+    let offset = u64::from_le_bytes(entry[..8].try_into().unwrap());
+    let length = u64::from_le_bytes(entry[8..].try_into().unwrap());
+    (offset, length)
+}
+
+/// Sequential reader of a table
+pub struct TableIterator<Item> {
+    data: BufReader<File>,
+    index: BufReader<File>,
+    remaining: usize,
+    buf: Vec<u8>,
     _phantom: PhantomData<Item>,
 }
 
-impl<'a, Item> Iterator for TableIterator<'a, Item> {
-    type Item = &'a Item;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next()
+impl<Item: DeserializeOwned> TableIterator<Item> {
+    fn read_next(&mut self) -> Result<Item> {
+        let mut entry = [0u8; INDEX_ENTRY_SIZE as usize];
+        self.index.read_exact(&mut entry)?;
+        let (_, length) = parse_entry(&entry);
+
+        self.buf.resize(length as usize, 0);
+        self.data.read_exact(&mut self.buf)?;
+        Ok(serde_json::from_slice(&self.buf)?)
     }
 }
 
-impl<'a, Item> IntoIterator for &'a Table<Item> {
-    type Item = &'a Item;
-    type IntoIter = TableIterator<'a, Item>;
+impl<Item: DeserializeOwned> Iterator for TableIterator<Item> {
+    type Item = Result<Item>;
 
-    fn into_iter(self) -> Self::IntoIter {
-        let iter = self.data.iter();
-        TableIterator {
-            iter: iter,
-            _phantom: PhantomData,
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
         }
+        self.remaining -= 1;
+        Some(self.read_next())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Table;
+    use super::*;
 
     #[test]
     fn test_iteration() {
-        let mut store: Table<i32> = Table::new();
-
-        store.append(1);
-        store.append(2);
-        store.append(3);
+        let mut store: Table<(i32, String)> = Table::temporary().unwrap();
 
-        for item in &store {
-            println!("{:?}", item)
+        for i in 0..100 {
+            store.push(&(i, "x".repeat(i as usize))).unwrap();
         }
+        assert_eq!(store.len(), 100);
+
+        let items: Vec<_> = store.iter().unwrap().map(|x| x.unwrap()).collect();
+        assert_eq!(items.len(), 100);
+        assert_eq!(items[42], (42, "x".repeat(42)));
+
+        assert_eq!(store.get(7).unwrap(), Some((7, "x".repeat(7))));
+        assert_eq!(store.get(100).unwrap(), None);
+
+        // Pushes after reading are visible to later reads
+        store.push(&(100, String::new())).unwrap();
+        assert_eq!(store.get(100).unwrap(), Some((100, String::new())));
+        assert_eq!(store.get(0).unwrap(), Some((0, String::new())));
+    }
+
+    #[test]
+    fn test_temporary_files_removed() {
+        let store: Table<i32> = Table::temporary().unwrap();
+        let path = store.data_path.clone();
+        assert!(path.exists());
+        drop(store);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_spill_space() {
+        let dir = std::env::temp_dir();
+        assert!(available_space(&dir).unwrap() > 0);
+        assert!(ensure_space(&dir, 0).is_ok());
+
+        let err = ensure_space(&dir, u64::MAX).unwrap_err();
+        assert!(err.to_string().contains(SPILL_DIR_ENV));
+    }
+
+    #[test]
+    fn test_temporary_in() {
+        let dir = std::env::temp_dir().join(format!("rdtfs-spill-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut store: Table<i32> = Table::temporary_in(&dir).unwrap();
+        store.push(&1).unwrap();
+        assert!(store.data_path.starts_with(&dir));
+        drop(store);
+
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_escaped_text() {
+        use crate::gtfs::{Color, Route, RouteType};
+
+        let mut store: Table<Route> = Table::temporary().unwrap();
+        let route = Route {
+            route_id: "r1".to_string(),
+            agency_id: "a1".to_string(),
+            route_short_name: None,
+            route_long_name: None,
+            route_desc: None,
+            route_type: RouteType::Bus,
+            route_url: None,
+            route_color: Some(Color::parse("\"red\"")),
+            route_text_color: None,
+            route_sort_order: None,
+            continuous_pickup: None,
+            continuous_drop_off: None,
+            ticketing_deep_link_id: None,
+        };
+        store.push(&route).unwrap();
+
+        let route = store.get(0).unwrap().unwrap();
+        assert_eq!(route.route_color.unwrap().as_str(), "\"red\"");
     }
 }
@@ -0,0 +1,171 @@
+/// Geographic helpers for working with stop and shape coordinates
+///
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Point {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Point { lat, lon }
+    }
+
+    /// Latitude and longitude are finite and within valid ranges
+    pub fn is_valid(&self) -> bool {
+        self.lat.is_finite()
+            && self.lon.is_finite()
+            && (-90.0..=90.0).contains(&self.lat)
+            && (-180.0..=180.0).contains(&self.lon)
+    }
+
+    /// Null island, commonly exported when coordinates are unknown
+    pub fn is_placeholder(&self) -> bool {
+        self.lat == 0.0 && self.lon == 0.0
+    }
+}
+
+/// Point with median latitude and longitude, robust to a few misplaced points
+pub fn median_point<'a, I: IntoIterator<Item = &'a Point>>(points: I) -> Option<Point> {
+    let (mut lats, mut lons): (Vec<f64>, Vec<f64>) =
+        points.into_iter().map(|x| (x.lat, x.lon)).unzip();
+
+    if lats.is_empty() {
+        return None;
+    }
+
+    lats.sort_by(f64::total_cmp);
+    lons.sort_by(f64::total_cmp);
+
+    Some(Point::new(lats[lats.len() / 2], lons[lons.len() / 2]))
+}
+
+/// Great-circle distance between two points in meters
+pub fn haversine_m(from: &Point, to: &Point) -> f64 {
+    let d_lat = (to.lat - from.lat).to_radians();
+    let d_lon = (to.lon - from.lon).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2)
+        + from.lat.to_radians().cos() * to.lat.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Closest location on a polyline
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    /// Index of the polyline segment containing the projected point
+    pub segment: usize,
+    /// Distance from the start of the polyline to the projected point
+    pub distance_along_m: f64,
+    /// Distance from the point to the polyline
+    pub offset_m: f64,
+}
+
+/// Distance from the polyline start to every vertex
+pub fn cumulative_distances(polyline: &[Point]) -> Vec<f64> {
+    let mut total = 0.0;
+    let mut result = Vec::with_capacity(polyline.len());
+
+    for (i, point) in polyline.iter().enumerate() {
+        if i > 0 {
+            total += haversine_m(&polyline[i - 1], point);
+        }
+        result.push(total);
+    }
+
+    result
+}
+
+/// Position along the segment (0..1) closest to the point, using a local planar approximation
+fn project_onto_segment(point: &Point, from: &Point, to: &Point) -> f64 {
+    let scale_lon = from.lat.to_radians().cos();
+
+    let (dx, dy) = ((to.lon - from.lon) * scale_lon, to.lat - from.lat);
+    let (px, py) = ((point.lon - from.lon) * scale_lon, point.lat - from.lat);
+
+    let length_sq = dx * dx + dy * dy;
+    if length_sq == 0.0 {
+        return 0.0;
+    }
+
+    ((px * dx + py * dy) / length_sq).clamp(0.0, 1.0)
+}
+
+/// Project point onto polyline, considering only segments starting from `from_segment`
+///
+/// Searching from the previous match keeps projections of consecutive stops monotonic
+/// on shapes which loop back on themselves.
+pub fn project_onto_polyline(
+    point: &Point,
+    polyline: &[Point],
+    cumulative_m: &[f64],
+    from_segment: usize,
+) -> Option<Projection> {
+    if polyline.len() == 1 {
+        return Some(Projection {
+            segment: 0,
+            distance_along_m: 0.0,
+            offset_m: haversine_m(point, &polyline[0]),
+        });
+    }
+
+    let mut best: Option<Projection> = None;
+
+    for segment in from_segment..polyline.len().saturating_sub(1) {
+        let (from, to) = (&polyline[segment], &polyline[segment + 1]);
+        let t = project_onto_segment(point, from, to);
+
+        let projected = Point::new(
+            from.lat + (to.lat - from.lat) * t,
+            from.lon + (to.lon - from.lon) * t,
+        );
+        let offset_m = haversine_m(point, &projected);
+
+        if best.is_none_or(|x| offset_m < x.offset_m) {
+            best = Some(Projection {
+                segment,
+                distance_along_m: cumulative_m[segment]
+                    + (cumulative_m[segment + 1] - cumulative_m[segment]) * t,
+                offset_m,
+            });
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cumulative_distances, haversine_m, project_onto_polyline, Point};
+
+    #[test]
+    fn test_haversine() {
+        let berlin = Point::new(52.5200, 13.4050);
+        let munich = Point::new(48.1351, 11.5820);
+
+        let distance = haversine_m(&berlin, &munich);
+        assert!((distance - 504_000.0).abs() < 2_000.0, "{distance}");
+        assert_eq!(haversine_m(&berlin, &berlin), 0.0);
+    }
+
+    #[test]
+    fn test_projection() {
+        // Straight line going east along the equator, ~111 km per degree
+        let line = [
+            Point::new(0.0, 0.0),
+            Point::new(0.0, 1.0),
+            Point::new(0.0, 2.0),
+        ];
+        let cumulative = cumulative_distances(&line);
+
+        let projection =
+            project_onto_polyline(&Point::new(0.01, 1.5), &line, &cumulative, 0).unwrap();
+
+        assert_eq!(projection.segment, 1);
+        assert!((projection.distance_along_m - cumulative[2] * 0.75).abs() < 10.0);
+        assert!((projection.offset_m - 1_112.0).abs() < 10.0);
+    }
+}
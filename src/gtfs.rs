@@ -3,18 +3,17 @@
 use std::{
     borrow::Borrow,
     cell::{Ref, RefCell},
-    collections::HashMap,
     fmt::Display,
     fs::{File, OpenOptions},
     io::{BufRead, BufReader, Read, Seek},
     marker::PhantomData,
     path::Path,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use chrono::NaiveDate;
-use indicatif::{ProgressBar, ProgressStyle};
 use rust_decimal::Decimal;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::value;
@@ -22,7 +21,22 @@ use serde_repr::{Deserialize_repr, Serialize_repr};
 use uuid::Uuid;
 use zip::{read::ZipFile, ZipArchive};
 
-use crate::csv::{row::FieldReference, CsvTableReader};
+use crate::cancel::{self, Cancelled};
+use crate::csv::{row::FieldReference, CsvError, CsvTableReader};
+use crate::hashing::FastHashMap;
+use crate::progress::{NoProgress, Progress, ProgressSink};
+use crate::summary;
+use crate::time::{self, LocalTimePolicy, TimeError};
+
+pub mod accessibility;
+pub mod calendar;
+pub mod departures;
+pub mod headsigns;
+pub mod shapes;
+pub mod stop_matching;
+pub mod synthetic;
+pub mod validation;
+pub mod writer;
 
 pub trait GtfsFile {
     fn get_file_type() -> GtfsFileType;
@@ -61,9 +75,83 @@ pub enum ContinuousDropOffType {
     AskDriver = 3,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Color {
-    Hex(String),
+    /// Six digit hex color, original text is kept for lossless serialization
+    Rgb {
+        red: u8,
+        green: u8,
+        blue: u8,
+        text: String,
+    },
+    /// Value that is not a six digit hex color
+    Invalid(String),
+}
+
+impl Color {
+    pub fn parse(value: &str) -> Self {
+        let channel = |i: usize| u8::from_str_radix(value.get(i..i + 2)?, 16).ok();
+
+        if value.len() != 6 || !value.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Color::Invalid(value.to_string());
+        }
+
+        match (channel(0), channel(2), channel(4)) {
+            (Some(red), Some(green), Some(blue)) => Color::Rgb {
+                red,
+                green,
+                blue,
+                text: value.to_string(),
+            },
+            _ => Color::Invalid(value.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Color::Rgb { text, .. } => text,
+            Color::Invalid(text) => text,
+        }
+    }
+
+    pub fn rgb(&self) -> Option<(u8, u8, u8)> {
+        match self {
+            Color::Rgb {
+                red, green, blue, ..
+            } => Some((*red, *green, *blue)),
+            Color::Invalid(_) => None,
+        }
+    }
+
+    /// Relative luminance as defined by WCAG 2
+    pub fn relative_luminance(&self) -> Option<f64> {
+        let (red, green, blue) = self.rgb()?;
+
+        let linear = |channel: u8| {
+            let value = channel as f64 / 255.0;
+            if value <= 0.03928 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        Some(0.2126 * linear(red) + 0.7152 * linear(green) + 0.0722 * linear(blue))
+    }
+
+    /// WCAG 2 contrast ratio between two colors, from 1 to 21
+    pub fn contrast_ratio(&self, other: &Color) -> Option<f64> {
+        let first = self.relative_luminance()?;
+        let second = other.relative_luminance()?;
+
+        let (lighter, darker) = if first > second {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        Some((lighter + 0.05) / (darker + 0.05))
+    }
 }
 
 impl Serialize for Color {
@@ -71,9 +159,7 @@ impl Serialize for Color {
     where
         S: serde::Serializer,
     {
-        match self {
-            Color::Hex(value) => value.serialize(serializer),
-        }
+        self.as_str().serialize(serializer)
     }
 }
 
@@ -82,8 +168,9 @@ impl<'de> Deserialize<'de> for Color {
     where
         D: serde::Deserializer<'de>,
     {
-        let value: &str = Deserialize::deserialize(deserializer)?;
-        Ok(Color::Hex(value.to_string()))
+        // Owned, escaped json text of spilled tables can not be borrowed
+        let value: String = Deserialize::deserialize(deserializer)?;
+        Ok(Color::parse(&value))
     }
 }
 
@@ -114,8 +201,8 @@ impl Route {
             route_desc: None,
             route_type: RouteType::Bus,
             route_url: None,
-            route_color: Some(Color::Hex("FFFFFF".to_string())),
-            route_text_color: Some(Color::Hex("BBBBBB".to_string())),
+            route_color: Some(Color::parse("FFFFFF")),
+            route_text_color: Some(Color::parse("BBBBBB")),
             route_sort_order: None,
             continuous_pickup: Some(ContinuousPickupType::NoContinuousStoppingPickup),
             continuous_drop_off: Some(ContinuousDropOffType::NoContinuousStoppingDropOff),
@@ -265,7 +352,8 @@ pub struct StopTime {
     pub trip_id: String,
     pub arrival_time: Option<String>,
     pub departure_time: Option<String>,
-    pub stop_id: String,
+    /// None on flex rows served at a location group or zone instead
+    pub stop_id: Option<String>,
     pub stop_sequence: u64,
     pub stop_headsign: Option<String>,
     pub pickup_type: Option<StopPickupType>,
@@ -275,6 +363,14 @@ pub struct StopTime {
     pub shape_dist_traveled: Option<f64>,
     pub timepoint: Option<TimePointType>,
     pub ticketing_type: Option<TicketingType>,
+    /// Flex: served at any stop of the group instead of stop_id
+    pub location_group_id: Option<String>,
+    /// Flex: served anywhere within a zone of locations.geojson
+    pub location_id: Option<String>,
+    pub start_pickup_drop_off_window: Option<String>,
+    pub end_pickup_drop_off_window: Option<String>,
+    pub pickup_booking_rule_id: Option<String>,
+    pub drop_off_booking_rule_id: Option<String>,
 }
 
 impl GtfsFile for StopTime {
@@ -283,6 +379,101 @@ impl GtfsFile for StopTime {
     }
 }
 
+/// Errors of reading feeds from a store
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("File {0} not found")]
+    FileNotFound(&'static str),
+    #[error("Duplicate file in zip: {0}")]
+    DuplicateFile(String),
+    #[error("Could not open {path}")]
+    Open {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Could not write {path}")]
+    Write {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid zip archive")]
+    Zip(#[from] zip::result::ZipError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GtfsError {
+    #[error("Invalid time {0}, expected HH:MM:SS")]
+    InvalidTime(String),
+    #[error("Invalid time {0}, minutes and seconds must be below 60 and the total fit in u32")]
+    TimeOutOfRange(String),
+    #[error("Invalid date {0}, expected YYYYMMDD")]
+    InvalidDate(String),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error(transparent)]
+    Csv(#[from] CsvError),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+    #[error(transparent)]
+    Time(#[from] TimeError),
+    #[error("Invalid locations.geojson")]
+    Locations(#[source] serde_json::Error),
+    /// Storage of a decompressed table failed
+    #[error(transparent)]
+    Table(anyhow::Error),
+}
+
+/// Parse HH:MM:SS time into seconds since start of the service day, hours may exceed 24
+pub fn parse_gtfs_time(value: &str) -> Result<u32, GtfsError> {
+    let invalid = || GtfsError::InvalidTime(value.to_string());
+    let mut parts = value.trim().split(':');
+
+    let (Some(hours), Some(minutes), Some(seconds), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    let seconds: u32 = seconds.parse().map_err(|_| invalid())?;
+
+    let out_of_range = || GtfsError::TimeOutOfRange(value.to_string());
+    if minutes >= 60 || seconds >= 60 {
+        return Err(out_of_range());
+    }
+
+    hours
+        .checked_mul(3600)
+        .and_then(|x| x.checked_add(minutes * 60 + seconds))
+        .ok_or_else(out_of_range)
+}
+
+/// Format seconds since start of the service day as HH:MM:SS
+pub fn format_gtfs_time(seconds: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Instant of a stop time, read as wall clock time since midnight of the service date in tz
+///
+/// Times falling into daylight saving transitions are resolved according to policy.
+pub fn resolve_stop_time(
+    date: NaiveDate,
+    seconds: u32,
+    tz: &chrono_tz::Tz,
+    policy: LocalTimePolicy,
+) -> Result<chrono::DateTime<chrono_tz::Tz>, GtfsError> {
+    let local = date.and_hms_opt(0, 0, 0).unwrap() + chrono::Duration::seconds(seconds.into());
+    Ok(time::resolve_local(tz, &local, policy)?)
+}
+
 #[derive(Debug, Deserialize_repr, Serialize_repr)]
 #[repr(u8)]
 pub enum ServiceAvailability {
@@ -600,20 +791,88 @@ impl GtfsFile for TicketingDeepLink {
     }
 }
 
-struct GtfsFullRouteInfo {
-    route: Route,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LocationGroup {
+    pub location_group_id: String,
+    pub location_group_name: Option<String>,
 }
 
-struct GtfsWriter {}
+impl GtfsFile for LocationGroup {
+    fn get_file_type() -> GtfsFileType {
+        GtfsFileType::LocationGroups
+    }
+}
 
-impl GtfsWriter {
-    /// Add full route info into gtfs collection
-    fn write_route(route: GtfsFullRouteInfo) {
-        todo!()
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LocationGroupStop {
+    pub location_group_id: String,
+    pub stop_id: String,
+}
+
+impl GtfsFile for LocationGroupStop {
+    fn get_file_type() -> GtfsFileType {
+        GtfsFileType::LocationGroupStops
     }
 }
 
-#[derive(Eq, Hash, PartialEq, Clone, Copy)]
+#[derive(Debug, Deserialize_repr, Serialize_repr)]
+#[repr(u8)]
+pub enum BookingType {
+    RealTime = 0,
+    SameDay = 1,
+    PriorDays = 2,
+}
+
+/// How far ahead and through which channels a flexible trip has to be booked
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookingRule {
+    pub booking_rule_id: String,
+    pub booking_type: BookingType,
+    /// Minutes, same day bookings only
+    pub prior_notice_duration_min: Option<u32>,
+    pub prior_notice_duration_max: Option<u32>,
+    /// Days, prior day bookings only
+    pub prior_notice_last_day: Option<u32>,
+    pub prior_notice_last_time: Option<String>,
+    pub prior_notice_start_day: Option<u32>,
+    pub prior_notice_start_time: Option<String>,
+    pub prior_notice_service_id: Option<String>,
+    pub message: Option<String>,
+    pub pickup_message: Option<String>,
+    pub drop_off_message: Option<String>,
+    pub phone_number: Option<String>,
+    pub info_url: Option<String>,
+    pub booking_url: Option<String>,
+}
+
+impl GtfsFile for BookingRule {
+    fn get_file_type() -> GtfsFileType {
+        GtfsFileType::BookingRules
+    }
+}
+
+/// Zone of a flexible service, a feature of locations.geojson
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Location {
+    pub id: String,
+    #[serde(default)]
+    pub properties: LocationProperties,
+    /// Polygon or MultiPolygon, kept as geojson
+    pub geometry: serde_json::Value,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LocationProperties {
+    pub stop_name: Option<String>,
+    pub stop_desc: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LocationCollection {
+    features: Vec<Location>,
+}
+
+#[derive(Debug, Eq, Hash, PartialEq, Clone, Copy)]
 pub enum GtfsFileType {
     Agencies,
     FeedInfos,
@@ -634,10 +893,15 @@ pub enum GtfsFileType {
     Levels,
     Translations,
     Attributions,
+    LocationGroups,
+    LocationGroupStops,
+    BookingRules,
+    /// Geojson rather than csv, read with GtfsStore::read_locations
+    Locations,
 }
 
 impl GtfsFileType {
-    fn file_name(&self) -> &str {
+    pub fn file_name(&self) -> &'static str {
         use GtfsFileType::*;
         match self {
             Agencies => "agency",
@@ -659,6 +923,10 @@ impl GtfsFileType {
             Levels => "levels",
             Translations => "translations",
             Attributions => "attributions",
+            LocationGroups => "location_groups",
+            LocationGroupStops => "location_group_stops",
+            BookingRules => "booking_rules",
+            Locations => "locations",
         }
     }
 
@@ -684,6 +952,10 @@ impl GtfsFileType {
             "levels" => Levels,
             "translations" => Translations,
             "attributions" => Attributions,
+            "location_groups" => LocationGroups,
+            "location_group_stops" => LocationGroupStops,
+            "booking_rules" => BookingRules,
+            "locations" => Locations,
             _ => {
                 log::warn!("Unkown filename: {}", name);
                 return None;
@@ -692,50 +964,191 @@ impl GtfsFileType {
     }
 }
 
+/// Number of malformed rows kept as examples per file
+const MAX_ROW_ERROR_SAMPLES: usize = 5;
+
+/// Rows of a single file which could not be deserialized
+#[derive(Debug)]
+pub struct RowErrors {
+    pub file_type: GtfsFileType,
+    pub count: usize,
+    pub samples: Vec<String>,
+}
+
+impl RowErrors {
+    fn new(file_type: GtfsFileType) -> Self {
+        RowErrors {
+            file_type,
+            count: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, line: usize, err: &CsvError) {
+        self.record_invalid(format!("line {line}: {err}"));
+    }
+
+    /// Count a row which was read but holds an unusable value
+    fn record_invalid(&mut self, sample: String) {
+        self.count += 1;
+        if self.samples.len() < MAX_ROW_ERROR_SAMPLES {
+            self.samples.push(sample);
+        }
+    }
+
+    pub fn log(&self) {
+        if self.count == 0 {
+            return;
+        }
+        log::warn!(
+            "Skipped {} malformed rows of {}",
+            self.count,
+            self.file_type.file_name()
+        );
+        for sample in &self.samples {
+            log::warn!("    {sample}");
+        }
+    }
+}
+
+/// Rows between two progress updates of a scan
+const PROGRESS_ROWS: u64 = 10_000;
+
 pub trait GtfsStore {
     fn get_readable<'a>(&'a mut self, file_type: GtfsFileType) -> Option<Box<dyn BufRead + 'a>>;
 
-    fn decompress<'a, I: DeserializeOwned + GtfsFile + 'static, F: TableFacory>(
+    /// Whether the file is present, stores reporting progress on open should check without opening
+    fn has_file(&mut self, file_type: GtfsFileType) -> bool {
+        self.get_readable(file_type).is_some()
+    }
+
+    /// Sink receiving progress of reading files, stages are named by file type
+    fn progress(&self) -> Arc<dyn ProgressSink> {
+        Arc::new(NoProgress)
+    }
+
+    fn decompress<'a, I: Serialize + DeserializeOwned + GtfsFile + 'static, F: TableFacory>(
         &mut self,
-    ) -> Result<Box<dyn Pushable<I>>> {
+    ) -> Result<Box<dyn Pushable<I>>, GtfsError> {
         let file_type = I::get_file_type();
-        let read = self.get_readable(file_type);
+        let started = Instant::now();
+        let mut table = F::new().map_err(GtfsError::Table)?;
+
+        let errors = self.scan::<I, _>(|item| table.push(item))?;
+        errors.log();
+        if let Some(err) = table.push_error() {
+            let err = anyhow!("Could not store {}: {err:#}", file_type.file_name());
+            return Err(GtfsError::Table(err));
+        }
 
-        let Some(read) = read else {
-            bail!("File {} not found", file_type.file_name())
+        tracing::info!(
+            file = file_type.file_name(),
+            items = table.length(),
+            "Decompressed {} items of {} in {:.2?}",
+            table.length(),
+            file_type.file_name(),
+            started.elapsed()
+        );
+        Ok(table)
+    }
+
+    /// Decompress a table, none if the file is not present
+    fn try_decompress<'a, I: Serialize + DeserializeOwned + GtfsFile + 'static, F: TableFacory>(
+        &mut self,
+    ) -> Result<Option<Box<dyn Pushable<I>>>, GtfsError> {
+        if !self.has_file(I::get_file_type()) {
+            return Ok(None);
+        }
+        self.decompress::<I, F>().map(Some)
+    }
+
+    /// Deserialize every row of a table, malformed rows are skipped and counted
+    fn scan<I: DeserializeOwned + GtfsFile, P: FnMut(I)>(
+        &mut self,
+        mut push: P,
+    ) -> Result<RowErrors, GtfsError> {
+        let file_type = I::get_file_type();
+        let progress = self.progress();
+        let span = tracing::debug_span!(
+            "scan",
+            file = file_type.file_name(),
+            rows = tracing::field::Empty,
+            errors = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+
+        let Some(read) = self.get_readable(file_type) else {
+            return Err(StoreError::FileNotFound(file_type.file_name()).into());
         };
-        println!("Decompressing {}", file_type.file_name());
-        let mut table = F::new();
 
-        let mut reader = CsvTableReader::new(read);
+        let mut reader = CsvTableReader::new(read)?;
         let mut buf = String::new();
         let mut field_buf = Vec::new();
+        let mut errors = RowErrors::new(file_type);
 
+        // Line 1 is the header
+        let mut line: u64 = 1;
         loop {
-            let next = match reader.read::<I>(&mut field_buf, &mut buf)? {
-                Some(value) => value,
-                None => break,
-            };
-            table.push(next);
+            line += 1;
+            if line.is_multiple_of(PROGRESS_ROWS) {
+                progress.on_items(file_type.file_name(), PROGRESS_ROWS);
+                cancel::check()?;
+            }
+            match reader.read::<I>(&mut field_buf, &mut buf) {
+                Ok(Some(item)) => push(item),
+                Ok(None) => break,
+                Err(err @ CsvError::Row { .. }) => errors.record(line as usize, &err),
+                Err(err) => return Err(err.into()),
+            }
         }
+        // Rows since the last update, without the header and the final read
+        progress.on_items(file_type.file_name(), (line - 2) % PROGRESS_ROWS);
+        span.record("rows", line - 2);
+        span.record("errors", errors.count);
+        summary::record_errors("malformed_row", errors.count);
 
-        println!("  Found {} items", table.length());
-        Ok(table)
+        Ok(errors)
     }
 
-    fn try_decompress<'a, I: DeserializeOwned + GtfsFile + 'static, F: TableFacory>(
+    /// Read whole table into memory along with rows that could not be parsed
+    fn scan_all<I: DeserializeOwned + GtfsFile>(
         &mut self,
-    ) -> Option<Box<dyn Pushable<I>>> {
-        match self.decompress::<I, F>() {
-            Ok(value) => Some(value),
-            Err(value) => None,
+    ) -> Result<(Vec<I>, RowErrors), GtfsError> {
+        let mut items = Vec::new();
+        let errors = self.scan(|item| items.push(item))?;
+        Ok((items, errors))
+    }
+
+    /// Read whole table into memory, malformed rows are logged and skipped
+    fn read_all<I: DeserializeOwned + GtfsFile>(&mut self) -> Result<Vec<I>, GtfsError> {
+        let (items, errors) = self.scan_all()?;
+        errors.log();
+        Ok(items)
+    }
+
+    /// Read whole table into memory, empty if the file is not present
+    fn try_read_all<I: DeserializeOwned + GtfsFile>(&mut self) -> Result<Vec<I>, GtfsError> {
+        if !self.has_file(I::get_file_type()) {
+            return Ok(Vec::new());
         }
+        self.read_all()
+    }
+
+    /// Zones of flexible services, empty if locations.geojson is not present
+    fn read_locations(&mut self) -> Result<Vec<Location>, GtfsError> {
+        let Some(read) = self.get_readable(GtfsFileType::Locations) else {
+            return Ok(Vec::new());
+        };
+        let collection: LocationCollection =
+            serde_json::from_reader(read).map_err(GtfsError::Locations)?;
+        Ok(collection.features)
     }
 }
 
 pub struct GtfsZipStore {
     archive: ZipArchive<File>,
-    file_name_mapping: HashMap<GtfsFileType, String>,
+    file_name_mapping: FastHashMap<GtfsFileType, String>,
+    progress: Arc<dyn ProgressSink>,
 }
 
 fn file_name_to_type(name: &str) -> Option<GtfsFileType> {
@@ -747,18 +1160,18 @@ fn file_name_to_type(name: &str) -> Option<GtfsFileType> {
 /// Retrieve file intexes for each of the gtfs file types
 fn get_file_names<'a, R: Read + Seek>(
     zip: &'a mut ZipArchive<R>,
-) -> Result<HashMap<GtfsFileType, String>> {
-    let mut mapping: HashMap<GtfsFileType, String> = HashMap::new();
+) -> Result<FastHashMap<GtfsFileType, String>, StoreError> {
+    let mut mapping: FastHashMap<GtfsFileType, String> = FastHashMap::default();
 
     for file_idx in 0..zip.len() {
-        let zipped_file = zip.by_index(file_idx).unwrap();
+        let zipped_file = zip.by_index(file_idx)?;
 
         let Some(file_type) = file_name_to_type(zipped_file.name()) else {
             continue
         };
 
         if let Some(value) = mapping.insert(file_type, zipped_file.name().to_string()) {
-            bail!("Duplicate file in zip: {}", zipped_file.name())
+            return Err(StoreError::DuplicateFile(zipped_file.name().to_string()));
         };
     }
 
@@ -766,35 +1179,51 @@ fn get_file_names<'a, R: Read + Seek>(
 }
 
 /// Reads data and reports progress
+///
+/// Bytes are counted once whether they are read or consumed from the buffer, reports stop
+/// at the declared total so that a member larger than declared cannot overshoot.
 struct ProgressReader<F> {
     consumed: u64,
+    total: u64,
     file: F,
-    bar: ProgressBar,
+    progress: Arc<dyn ProgressSink>,
+    stage: &'static str,
 }
 
 impl<F> ProgressReader<F> {
-    fn new(file: F, total_size: u64) -> Self {
-        let progress = ProgressBar::new(total_size);
-
-        progress.set_style(
-            ProgressStyle::with_template(
-                "{bar:40.cyan/blue} {bytes:>7}/{total_bytes:7} {binary_bytes_per_sec} [ETA: {eta}] {msg}",
-            )
-            .unwrap()
-            .progress_chars("##-"),
-        );
-
+    fn new(file: F, progress: Arc<dyn ProgressSink>, stage: &'static str, total: u64) -> Self {
+        progress.on_stage(stage, total);
         ProgressReader {
             consumed: 0,
+            total,
             file,
-            bar: progress,
+            progress,
+            stage,
+        }
+    }
+}
+
+impl<F> ProgressReader<F> {
+    fn advance(&mut self, amt: usize) {
+        let amt = (amt as u64).min(self.total.saturating_sub(self.consumed));
+        self.consumed += amt;
+        if amt > 0 {
+            self.progress.on_bytes(self.stage, amt);
         }
     }
 }
 
+impl<F> Drop for ProgressReader<F> {
+    fn drop(&mut self) {
+        self.progress.on_stage_end(self.stage);
+    }
+}
+
 impl<F: Read> Read for ProgressReader<F> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.file.read(buf)
+        let read = self.file.read(buf)?;
+        self.advance(read);
+        Ok(read)
     }
 }
 
@@ -804,41 +1233,62 @@ impl<F: BufRead> BufRead for ProgressReader<F> {
     }
 
     fn consume(&mut self, amt: usize) {
-        match TryInto::<u64>::try_into(amt) {
-            Ok(value) => self.bar.inc(value),
-            Err(_) => self.bar.inc(u64::MAX),
-        };
-
+        self.advance(amt);
         self.file.consume(amt);
     }
 }
 
 impl GtfsZipStore {
-    pub fn from_file(path: &str) -> Self {
-        let file = OpenOptions::new().read(true).open(path).unwrap();
+    pub fn from_file(path: &str) -> Result<Self, StoreError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(|source| StoreError::Open {
+                path: path.to_string(),
+                source,
+            })?;
 
-        let mut archive = zip::ZipArchive::new(file).unwrap();
+        let mut archive = zip::ZipArchive::new(file)?;
 
-        let file_name_mapping = get_file_names(&mut archive).unwrap();
+        let file_name_mapping = get_file_names(&mut archive)?;
 
-        GtfsZipStore {
+        Ok(GtfsZipStore {
             archive,
             file_name_mapping,
-        }
+            progress: Arc::new(Progress::default()),
+        })
+    }
+
+    /// Report progress of reading files to a sink shared with other stages of a pipeline
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = progress;
+        self
     }
 }
 
 impl GtfsStore for GtfsZipStore {
+    fn progress(&self) -> Arc<dyn ProgressSink> {
+        self.progress.clone()
+    }
+
+    fn has_file(&mut self, file_type: GtfsFileType) -> bool {
+        self.file_name_mapping.contains_key(&file_type)
+    }
+
     fn get_readable<'a>(&'a mut self, file_type: GtfsFileType) -> Option<Box<dyn BufRead + 'a>> {
         let Some(filename) = self.file_name_mapping.get(&file_type) else {
             return None
         };
 
         let res = self.archive.by_name(filename).unwrap();
+        let total = res.size();
 
-        let total_size = res.size();
-
-        let progress_reader = Box::new(ProgressReader::new(BufReader::new(res), total_size));
+        let progress_reader = Box::new(ProgressReader::new(
+            BufReader::new(res),
+            self.progress.clone(),
+            file_type.file_name(),
+            total,
+        ));
 
         Some(progress_reader)
     }
@@ -847,10 +1297,16 @@ impl GtfsStore for GtfsZipStore {
 pub trait Pushable<I> {
     fn push(&mut self, item: I);
     fn length(&self) -> usize;
+
+    /// First failed push, records pushed after it are missing
+    fn push_error(&self) -> Option<&anyhow::Error> {
+        None
+    }
 }
 
 pub trait TableFacory {
-    fn new<I: 'static>() -> Box<dyn Pushable<I>>;
+    /// Empty table, errors if no storage is available for it
+    fn new<I: Serialize + DeserializeOwned + 'static>() -> Result<Box<dyn Pushable<I>>>;
 }
 
 pub struct GtfsCollection {
@@ -873,17 +1329,22 @@ pub struct GtfsCollection {
     attributions: Option<Box<dyn Pushable<Attribution>>>,
     ticketing_identifiers: Option<Box<dyn Pushable<TicketingIdentifier>>>,
     ticketing_deep_links: Option<Box<dyn Pushable<TicketingDeepLink>>>,
+    /// Flex tables are small lookups kept in memory, empty if not present
+    location_groups: Vec<LocationGroup>,
+    location_group_stops: Vec<LocationGroupStop>,
+    booking_rules: Vec<BookingRule>,
+    locations: Vec<Location>,
 }
 
-fn decompress<'a, I: DeserializeOwned + 'static, F: TableFacory>(
+fn decompress<'a, I: Serialize + DeserializeOwned + 'static, F: TableFacory>(
     read: Option<Box<dyn BufRead + 'a>>,
 ) -> Result<Box<dyn Pushable<I>>> {
     let Some(read) = read else {
         bail!("File not found")
     };
     log::info!("Decompressing items");
-    let mut reader = CsvTableReader::new(read);
-    let mut table = F::new();
+    let mut reader = CsvTableReader::new(read)?;
+    let mut table = F::new()?;
 
     let mut buf = String::new();
     let mut field_buf = Vec::new();
@@ -895,12 +1356,15 @@ fn decompress<'a, I: DeserializeOwned + 'static, F: TableFacory>(
         };
         table.push(next);
     }
+    if let Some(err) = table.push_error() {
+        bail!("Could not store items: {err:#}");
+    }
 
     log::info!("Found {} items", table.length());
     Ok(table)
 }
 
-fn try_decompress<'a, I: DeserializeOwned + 'static, F: TableFacory>(
+fn try_decompress<'a, I: Serialize + DeserializeOwned + 'static, F: TableFacory>(
     read: Option<Box<dyn BufRead + 'a>>,
 ) -> Option<Box<dyn Pushable<I>>> {
     match decompress::<I, F>(read) {
@@ -920,20 +1384,24 @@ impl GtfsCollection {
         let routes = store.decompress::<Route, F>()?;
         let trips = store.decompress::<Trip, F>()?;
         let stop_times = store.decompress::<StopTime, F>()?;
-        let calendar = store.try_decompress::<Calendar, F>();
-        let calendar_dates = store.try_decompress::<CalendarDate, F>();
-        let fare_attributes = store.try_decompress::<FareAttribute, F>();
-        let fare_rules = store.try_decompress::<FareRule, F>();
-        let shapes = store.try_decompress::<Shape, F>();
-        let frequencies = store.try_decompress::<Frequency, F>();
-        let transfers = store.try_decompress::<Transfer, F>();
-        let pathways = store.try_decompress::<PathWay, F>();
-        let levels = store.try_decompress::<Level, F>();
-        let feed_info = store.try_decompress::<FeedInfo, F>();
-        let translations = store.try_decompress::<Translation, F>();
-        let attributions = store.try_decompress::<Attribution, F>();
-        let ticketing_identifiers = store.try_decompress::<TicketingIdentifier, F>();
-        let ticketing_deep_links = store.try_decompress::<TicketingDeepLink, F>();
+        let calendar = store.try_decompress::<Calendar, F>()?;
+        let calendar_dates = store.try_decompress::<CalendarDate, F>()?;
+        let fare_attributes = store.try_decompress::<FareAttribute, F>()?;
+        let fare_rules = store.try_decompress::<FareRule, F>()?;
+        let shapes = store.try_decompress::<Shape, F>()?;
+        let frequencies = store.try_decompress::<Frequency, F>()?;
+        let transfers = store.try_decompress::<Transfer, F>()?;
+        let pathways = store.try_decompress::<PathWay, F>()?;
+        let levels = store.try_decompress::<Level, F>()?;
+        let feed_info = store.try_decompress::<FeedInfo, F>()?;
+        let translations = store.try_decompress::<Translation, F>()?;
+        let attributions = store.try_decompress::<Attribution, F>()?;
+        let ticketing_identifiers = store.try_decompress::<TicketingIdentifier, F>()?;
+        let ticketing_deep_links = store.try_decompress::<TicketingDeepLink, F>()?;
+        let location_groups = store.try_read_all()?;
+        let location_group_stops = store.try_read_all()?;
+        let booking_rules = store.try_read_all()?;
+        let locations = store.read_locations()?;
 
         Ok(GtfsCollection {
             agency,
@@ -955,6 +1423,295 @@ impl GtfsCollection {
             attributions,
             ticketing_identifiers,
             ticketing_deep_links,
+            location_groups,
+            location_group_stops,
+            booking_rules,
+            locations,
         })
     }
+
+    pub fn location_groups(&self) -> &[LocationGroup] {
+        &self.location_groups
+    }
+
+    pub fn location_group_stops(&self) -> &[LocationGroupStop] {
+        &self.location_group_stops
+    }
+
+    /// Stops of a location group
+    pub fn location_group_stop_ids<'a>(
+        &'a self,
+        location_group_id: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.location_group_stops
+            .iter()
+            .filter(move |x| x.location_group_id == location_group_id)
+            .map(|x| x.stop_id.as_str())
+    }
+
+    pub fn booking_rules(&self) -> &[BookingRule] {
+        &self.booking_rules
+    }
+
+    pub fn booking_rule(&self, booking_rule_id: &str) -> Option<&BookingRule> {
+        self.booking_rules
+            .iter()
+            .find(|x| x.booking_rule_id == booking_rule_id)
+    }
+
+    /// Zones of locations.geojson
+    pub fn locations(&self) -> &[Location] {
+        &self.locations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Mutex};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingSink {
+        bytes: Mutex<u64>,
+        stages: Mutex<Vec<String>>,
+    }
+
+    impl ProgressSink for CountingSink {
+        fn on_stage(&self, stage: &str, total_bytes: u64) {
+            self.stages.lock().unwrap().push(stage.to_string());
+        }
+        fn on_bytes(&self, stage: &str, bytes: u64) {
+            *self.bytes.lock().unwrap() += bytes;
+        }
+        fn on_items(&self, stage: &str, items: u64) {}
+    }
+
+    #[test]
+    fn test_progress_reader_caps_at_total() {
+        let sink = Arc::new(CountingSink::default());
+        let data = "stop_id\n1\n2\n".repeat(100);
+        let mut reader = ProgressReader::new(
+            BufReader::new(Cursor::new(data.clone())),
+            sink.clone(),
+            "stops",
+            data.len() as u64 - 10,
+        );
+
+        // Mixed reads through the buffer and directly
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+
+        assert_eq!(line.len() + rest.len(), data.len());
+        assert_eq!(*sink.bytes.lock().unwrap(), data.len() as u64 - 10);
+    }
+
+    #[test]
+    fn test_resolve_stop_time() {
+        // Clocks in Berlin went from 03:00 back to 02:00 on 2023-10-29
+        let date = NaiveDate::from_ymd_opt(2023, 10, 29).unwrap();
+        let tz = chrono_tz::Europe::Berlin;
+        let latest = LocalTimePolicy {
+            ambiguous: time::Ambiguous::Latest,
+            ..Default::default()
+        };
+
+        let resolved = resolve_stop_time(date, parse_gtfs_time("02:30:00").unwrap(), &tz, latest);
+        assert_eq!(resolved.unwrap().to_rfc3339(), "2023-10-29T02:30:00+01:00");
+        assert!(matches!(
+            resolve_stop_time(date, 9000, &tz, LocalTimePolicy::default()),
+            Err(GtfsError::Time(TimeError::Ambiguous(..)))
+        ));
+
+        // Past midnight times fall on the next day
+        let resolved = resolve_stop_time(date, parse_gtfs_time("25:00:00").unwrap(), &tz, latest);
+        assert_eq!(resolved.unwrap().to_rfc3339(), "2023-10-30T01:00:00+01:00");
+    }
+
+    #[test]
+    fn test_parse_gtfs_time() {
+        assert_eq!(parse_gtfs_time(" 25:01:02").unwrap(), 90062);
+        assert!(matches!(
+            parse_gtfs_time("10:60:00"),
+            Err(GtfsError::TimeOutOfRange(_))
+        ));
+        // Hours overflowing the seconds counter
+        assert!(matches!(
+            parse_gtfs_time("9999999:00:00"),
+            Err(GtfsError::TimeOutOfRange(_))
+        ));
+        assert!(matches!(
+            parse_gtfs_time("10:00"),
+            Err(GtfsError::InvalidTime(_))
+        ));
+    }
+
+    struct FullDiskFactory;
+
+    impl TableFacory for FullDiskFactory {
+        fn new<I: Serialize + DeserializeOwned + 'static>() -> Result<Box<dyn Pushable<I>>> {
+            bail!("Only 1 MiB free in spill dir")
+        }
+    }
+
+    /// Table failing once it holds two records, like a disk filling up
+    #[derive(Default)]
+    struct FillingTable {
+        len: usize,
+        error: Option<anyhow::Error>,
+    }
+
+    impl<I> Pushable<I> for FillingTable {
+        fn push(&mut self, item: I) {
+            match self.len {
+                2 => self.error = Some(anyhow!("No space left on device")),
+                _ => self.len += 1,
+            }
+        }
+
+        fn length(&self) -> usize {
+            self.len
+        }
+
+        fn push_error(&self) -> Option<&anyhow::Error> {
+            self.error.as_ref()
+        }
+    }
+
+    struct FillingFactory;
+
+    impl TableFacory for FillingFactory {
+        fn new<I: Serialize + DeserializeOwned + 'static>() -> Result<Box<dyn Pushable<I>>> {
+            Ok(Box::<FillingTable>::default())
+        }
+    }
+
+    #[test]
+    fn test_push_error() {
+        let mut feed = synthetic::SyntheticFeed::generate(&Default::default());
+        let result = feed.decompress::<Stop, FillingFactory>();
+        let Err(GtfsError::Table(err)) = result else {
+            panic!("Expected a table error");
+        };
+        assert!(format!("{err:#}").contains("No space left"));
+    }
+
+    #[test]
+    fn test_try_decompress_errors() {
+        let mut feed = synthetic::SyntheticFeed::generate(&Default::default());
+        // Only a missing file is skipped
+        assert!(feed
+            .try_decompress::<Level, FillingFactory>()
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            feed.try_decompress::<Stop, FillingFactory>(),
+            Err(GtfsError::Table(_))
+        ));
+    }
+
+    #[test]
+    fn test_table_factory_error() {
+        let mut feed = synthetic::SyntheticFeed::generate(&Default::default());
+        let Err(err) = GtfsCollection::from_store::<_, FullDiskFactory>(&mut feed) else {
+            panic!("Expected an error");
+        };
+        assert!(err.to_string().contains("spill dir"));
+    }
+
+    #[test]
+    fn test_flex_files() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("rdtfs-flex-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("flex.zip");
+
+        let files = [
+            (
+                "agency.txt",
+                "agency_id,agency_name,agency_url,agency_timezone\n",
+            ),
+            ("stops.txt", "stop_id\n"),
+            ("routes.txt", "route_id,route_type\n"),
+            ("trips.txt", "route_id,service_id,trip_id\n"),
+            (
+                "location_groups.txt",
+                "location_group_id,location_group_name\ng1,Old town\n",
+            ),
+            (
+                "location_group_stops.txt",
+                "location_group_id,stop_id\ng1,s1\ng1,s2\n",
+            ),
+            (
+                "booking_rules.txt",
+                "booking_rule_id,booking_type,prior_notice_duration_min,phone_number\n\
+                b1,1,60,+49 30 1234\n",
+            ),
+            (
+                "stop_times.txt",
+                "trip_id,stop_id,stop_sequence,location_group_id,\
+                start_pickup_drop_off_window,end_pickup_drop_off_window,pickup_booking_rule_id\n\
+                t1,,1,g1,08:00:00,12:00:00,b1\n",
+            ),
+            (
+                "locations.geojson",
+                r#"{"type": "FeatureCollection", "features": [{"type": "Feature", "id": "z1",
+                "properties": {"stop_name": "Zone 1"},
+                "geometry": {"type": "Polygon", "coordinates": [[[13.4, 52.5], [13.5, 52.5], [13.4, 52.6], [13.4, 52.5]]]}}]}"#,
+            ),
+        ];
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, text) in files {
+            zip.start_file(name, Default::default()).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let mut store = GtfsZipStore::from_file(path.to_str().unwrap()).unwrap();
+        let groups: Vec<LocationGroup> = store.read_all().unwrap();
+        assert_eq!(groups[0].location_group_name.as_deref(), Some("Old town"));
+        let group_stops: Vec<LocationGroupStop> = store.read_all().unwrap();
+        assert_eq!(group_stops.len(), 2);
+        let rules: Vec<BookingRule> = store.read_all().unwrap();
+        assert!(matches!(rules[0].booking_type, BookingType::SameDay));
+        assert_eq!(rules[0].prior_notice_duration_min, Some(60));
+
+        let stop_times: Vec<StopTime> = store.read_all().unwrap();
+        assert_eq!(stop_times[0].location_group_id.as_deref(), Some("g1"));
+        assert_eq!(
+            stop_times[0].end_pickup_drop_off_window.as_deref(),
+            Some("12:00:00")
+        );
+
+        let locations = store.read_locations().unwrap();
+        assert_eq!(locations[0].id, "z1");
+        assert_eq!(locations[0].properties.stop_name.as_deref(), Some("Zone 1"));
+        assert_eq!(locations[0].geometry["type"], "Polygon");
+
+        let collection = GtfsCollection::from_store::<_, FillingFactory>(&mut store).unwrap();
+        assert_eq!(collection.location_groups().len(), 1);
+        let stop_ids: Vec<_> = collection.location_group_stop_ids("g1").collect();
+        assert_eq!(stop_ids, ["s1", "s2"]);
+        let rule = collection.booking_rule("b1").unwrap();
+        assert_eq!(rule.phone_number.as_deref(), Some("+49 30 1234"));
+        assert_eq!(collection.locations()[0].id, "z1");
+
+        // Absent optional files do not start a progress stage
+        let sink = Arc::new(CountingSink::default());
+        let mut store = GtfsZipStore::from_file(path.to_str().unwrap())
+            .unwrap()
+            .with_progress(sink.clone());
+        assert!(store
+            .try_decompress::<Level, FillingFactory>()
+            .unwrap()
+            .is_none());
+        assert!(store.try_read_all::<Calendar>().unwrap().is_empty());
+        store.read_all::<Stop>().unwrap();
+        assert_eq!(*sink.stages.lock().unwrap(), ["stops"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,167 @@
+/// Aggregation of wheelchair and bicycle accessibility of trips
+///
+use std::fmt::Display;
+
+use super::{BikesAllowedType, Route, Trip, WheelChairBoardingType};
+use crate::hashing::FastHashMap;
+
+/// Share of trips supporting a feature
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Coverage {
+    Full,
+    Partial,
+    NotSupported,
+    Unknown,
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Coverage::Full => "full",
+            Coverage::Partial => "partial",
+            Coverage::NotSupported => "none",
+            Coverage::Unknown => "unknown",
+        };
+        f.pad(value)
+    }
+}
+
+#[derive(Default)]
+struct Counter {
+    supported: usize,
+    not_supported: usize,
+    total: usize,
+}
+
+impl Counter {
+    fn add(&mut self, supported: Option<bool>) {
+        self.total += 1;
+        match supported {
+            Some(true) => self.supported += 1,
+            Some(false) => self.not_supported += 1,
+            None => (),
+        }
+    }
+
+    fn coverage(&self) -> Coverage {
+        if self.total > 0 && self.supported == self.total {
+            Coverage::Full
+        } else if self.supported > 0 {
+            Coverage::Partial
+        } else if self.total > 0 && self.not_supported == self.total {
+            Coverage::NotSupported
+        } else {
+            Coverage::Unknown
+        }
+    }
+}
+
+fn wheelchair_supported(trip: &Trip) -> Option<bool> {
+    match trip.wheelchair_accessible {
+        Some(WheelChairBoardingType::WheelchairSupported) => Some(true),
+        Some(WheelChairBoardingType::NoWheelchairSupport) => Some(false),
+        Some(WheelChairBoardingType::NoInformation) | None => None,
+    }
+}
+
+fn bikes_supported(trip: &Trip) -> Option<bool> {
+    match trip.bikes_allowed {
+        Some(BikesAllowedType::BikesAllowed) => Some(true),
+        Some(BikesAllowedType::NoBikesAllowed) => Some(false),
+        Some(BikesAllowedType::NoInformation) | None => None,
+    }
+}
+
+pub struct RouteAccessibility {
+    pub route_id: String,
+    pub trips: usize,
+    pub wheelchair: Coverage,
+    pub bikes: Coverage,
+}
+
+/// Accessibility of every route, ordered by route_id
+pub fn accessibility_by_route(trips: &[Trip]) -> Vec<RouteAccessibility> {
+    let mut counters: FastHashMap<&str, (Counter, Counter)> = FastHashMap::default();
+
+    for trip in trips {
+        let (wheelchair, bikes) = counters.entry(trip.route_id.as_str()).or_default();
+        wheelchair.add(wheelchair_supported(trip));
+        bikes.add(bikes_supported(trip));
+    }
+
+    let mut result: Vec<RouteAccessibility> = counters
+        .into_iter()
+        .map(|(route_id, (wheelchair, bikes))| RouteAccessibility {
+            route_id: route_id.to_string(),
+            trips: wheelchair.total,
+            wheelchair: wheelchair.coverage(),
+            bikes: bikes.coverage(),
+        })
+        .collect();
+
+    result.sort_by(|a, b| a.route_id.cmp(&b.route_id));
+    result
+}
+
+/// Print route summary with accessibility of its trips
+pub fn print_route_report(routes: &[Route], trips: &[Trip]) {
+    let names: FastHashMap<&str, &str> = routes
+        .iter()
+        .map(|route| {
+            let name = route
+                .route_short_name
+                .as_deref()
+                .or(route.route_long_name.as_deref())
+                .unwrap_or_default();
+            (route.route_id.as_str(), name)
+        })
+        .collect();
+
+    println!(
+        "{:<40} {:<20} {:>8} {:>10} {:>10}",
+        "route_id", "name", "trips", "wheelchair", "bikes"
+    );
+
+    for route in accessibility_by_route(trips) {
+        println!(
+            "{:<40} {:<20} {:>8} {:>10} {:>10}",
+            route.route_id,
+            names.get(route.route_id.as_str()).unwrap_or(&""),
+            route.trips,
+            route.wheelchair,
+            route.bikes
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+    use crate::gtfs::{BikesAllowedType, WheelChairBoardingType};
+
+    use super::{accessibility_by_route, Coverage};
+
+    #[test]
+    fn test_coverage() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 2,
+            ..Default::default()
+        });
+
+        for trip in feed.trips.iter_mut() {
+            trip.wheelchair_accessible = Some(WheelChairBoardingType::WheelchairSupported);
+            if trip.route_id == "route-1" {
+                trip.bikes_allowed = Some(BikesAllowedType::NoBikesAllowed);
+            }
+        }
+        feed.trips[0].wheelchair_accessible = Some(WheelChairBoardingType::NoWheelchairSupport);
+
+        let result = accessibility_by_route(&feed.trips);
+
+        assert_eq!(result[0].route_id, "route-0");
+        assert_eq!(result[0].wheelchair, Coverage::Partial);
+        assert_eq!(result[0].bikes, Coverage::Unknown);
+        assert_eq!(result[1].wheelchair, Coverage::Full);
+        assert_eq!(result[1].bikes, Coverage::NotSupported);
+    }
+}
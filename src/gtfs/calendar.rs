@@ -0,0 +1,388 @@
+/// Conversion between sets of operating dates and calendar/calendar_dates records
+///
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+use super::{Calendar, CalendarDate, GtfsError, SerivceExceptionType, ServiceAvailability};
+
+const GTFS_DATE_FORMAT: &str = "%Y%m%d";
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Most compact calendar representation of a set of dates
+#[derive(Debug)]
+pub struct CompressedCalendar {
+    /// Weekly pattern, absent when the dates are best expressed as exceptions only
+    pub calendar: Option<Calendar>,
+    /// Exceptions to the weekly pattern
+    pub calendar_dates: Vec<CalendarDate>,
+}
+
+pub fn parse_gtfs_date(value: &str) -> Result<NaiveDate, GtfsError> {
+    NaiveDate::parse_from_str(value, GTFS_DATE_FORMAT)
+        .map_err(|_| GtfsError::InvalidDate(value.to_string()))
+}
+
+pub fn format_gtfs_date(date: &NaiveDate) -> String {
+    date.format(GTFS_DATE_FORMAT).to_string()
+}
+
+fn availability(value: bool) -> ServiceAvailability {
+    if value {
+        ServiceAvailability::SeriviceAvailable
+    } else {
+        ServiceAvailability::SeriviceNotAvailable
+    }
+}
+
+impl Calendar {
+    /// Calendar running on the weekdays enabled in the mask, starting from monday
+    pub fn weekly(
+        service_id: &str,
+        start: &NaiveDate,
+        end: &NaiveDate,
+        weekdays: [bool; 7],
+    ) -> Self {
+        Calendar {
+            service_id: service_id.to_string(),
+            start_date: format_gtfs_date(start),
+            end_date: format_gtfs_date(end),
+            monday: availability(weekdays[0]),
+            tuesday: availability(weekdays[1]),
+            wednesday: availability(weekdays[2]),
+            thursday: availability(weekdays[3]),
+            friday: availability(weekdays[4]),
+            saturday: availability(weekdays[5]),
+            sunday: availability(weekdays[6]),
+        }
+    }
+
+    /// Check if the weekly pattern enables at least one weekday
+    pub fn runs_on_any_weekday(&self) -> bool {
+        WEEKDAYS.iter().any(|x| self.runs_on(*x))
+    }
+
+    /// Check if service runs on given weekday according to the weekly pattern
+    pub fn runs_on(&self, weekday: Weekday) -> bool {
+        let value = match weekday {
+            Weekday::Mon => &self.monday,
+            Weekday::Tue => &self.tuesday,
+            Weekday::Wed => &self.wednesday,
+            Weekday::Thu => &self.thursday,
+            Weekday::Fri => &self.friday,
+            Weekday::Sat => &self.saturday,
+            Weekday::Sun => &self.sunday,
+        };
+        matches!(value, ServiceAvailability::SeriviceAvailable)
+    }
+}
+
+/// Running totals of days per weekday, used to count days in a range in O(1)
+struct WeekdayPrefix {
+    first_day: NaiveDate,
+    // counts[i][w] - number of matching days before first_day + i with weekday w
+    total: Vec<[usize; 7]>,
+    operating: Vec<[usize; 7]>,
+}
+
+impl WeekdayPrefix {
+    fn new(dates: &BTreeSet<NaiveDate>, first_day: NaiveDate, last_day: NaiveDate) -> Self {
+        let num_days = (last_day - first_day).num_days() as usize + 1;
+
+        let mut total = Vec::with_capacity(num_days + 1);
+        let mut operating = Vec::with_capacity(num_days + 1);
+
+        let mut total_acc = [0; 7];
+        let mut operating_acc = [0; 7];
+
+        total.push(total_acc);
+        operating.push(operating_acc);
+
+        for day_i in 0..num_days {
+            let day = first_day + Duration::days(day_i as i64);
+            let weekday = day.weekday().num_days_from_monday() as usize;
+            total_acc[weekday] += 1;
+            if dates.contains(&day) {
+                operating_acc[weekday] += 1;
+            }
+            total.push(total_acc);
+            operating.push(operating_acc);
+        }
+
+        WeekdayPrefix {
+            first_day,
+            total,
+            operating,
+        }
+    }
+
+    fn index(&self, date: &NaiveDate) -> usize {
+        (*date - self.first_day).num_days() as usize
+    }
+
+    /// Number of (total, operating) days per weekday in the inclusive range
+    fn counts(&self, start: &NaiveDate, end: &NaiveDate) -> ([usize; 7], [usize; 7]) {
+        let from = self.index(start);
+        let to = self.index(end) + 1;
+
+        let mut total = [0; 7];
+        let mut operating = [0; 7];
+
+        for weekday in 0..7 {
+            total[weekday] = self.total[to][weekday] - self.total[from][weekday];
+            operating[weekday] = self.operating[to][weekday] - self.operating[from][weekday];
+        }
+
+        (total, operating)
+    }
+}
+
+/// Number of exceptions needed to express dates in a range with the best weekday mask
+fn range_cost(total: &[usize; 7], operating: &[usize; 7]) -> (usize, [bool; 7]) {
+    let mut cost = 0;
+    let mut mask = [false; 7];
+
+    for weekday in 0..7 {
+        let removed = total[weekday] - operating[weekday];
+        let added = operating[weekday];
+        if removed < added {
+            mask[weekday] = true;
+            cost += removed;
+        } else {
+            cost += added;
+        }
+    }
+
+    (cost, mask)
+}
+
+/// Derive the calendar with the least number of records for a set of dates
+///
+/// Every candidate validity range spanning between two operating dates is considered,
+/// dates outside of the chosen range are added as exceptions. The calendar row itself
+/// counts as a record, so it is only emitted when it saves exceptions.
+pub fn compress_dates(service_id: &str, dates: &BTreeSet<NaiveDate>) -> CompressedCalendar {
+    let (Some(&first_day), Some(&last_day)) = (dates.first(), dates.last()) else {
+        return CompressedCalendar {
+            calendar: None,
+            calendar_dates: Vec::new(),
+        };
+    };
+
+    let prefix = WeekdayPrefix::new(dates, first_day, last_day);
+    let sorted: Vec<&NaiveDate> = dates.iter().collect();
+
+    // Without a weekly pattern every date is an exception
+    let mut best_cost = dates.len();
+    let mut best: Option<(NaiveDate, NaiveDate, [bool; 7])> = None;
+
+    for (start_i, start) in sorted.iter().enumerate() {
+        for (end_i, end) in sorted.iter().enumerate().skip(start_i) {
+            let (total, operating) = prefix.counts(start, end);
+            let (cost, mask) = range_cost(&total, &operating);
+            if !mask.iter().any(|x| *x) {
+                continue;
+            }
+            let outside = start_i + (sorted.len() - end_i - 1);
+            if cost + outside + 1 < best_cost {
+                best_cost = cost + outside + 1;
+                best = Some((**start, **end, mask));
+            }
+        }
+    }
+
+    let Some((start, end, mask)) = best else {
+        return CompressedCalendar {
+            calendar: None,
+            calendar_dates: dates
+                .iter()
+                .map(|date| CalendarDate {
+                    service_id: service_id.to_string(),
+                    date: format_gtfs_date(date),
+                    exception_type: SerivceExceptionType::Added,
+                })
+                .collect(),
+        };
+    };
+
+    let mut calendar_dates = Vec::with_capacity(best_cost);
+
+    let mut day = first_day;
+    while day <= last_day {
+        let in_range = start <= day && day <= end;
+        let in_pattern = in_range && mask[day.weekday().num_days_from_monday() as usize];
+        let operating = dates.contains(&day);
+
+        let exception_type = match (operating, in_pattern) {
+            (true, false) => Some(SerivceExceptionType::Added),
+            (false, true) => Some(SerivceExceptionType::Removed),
+            _ => None,
+        };
+
+        if let Some(exception_type) = exception_type {
+            calendar_dates.push(CalendarDate {
+                service_id: service_id.to_string(),
+                date: format_gtfs_date(&day),
+                exception_type,
+            });
+        }
+
+        day += Duration::days(1);
+    }
+
+    CompressedCalendar {
+        calendar: Some(Calendar::weekly(service_id, &start, &end, mask)),
+        calendar_dates,
+    }
+}
+
+/// Expand calendar and its exceptions into the set of operating dates
+pub fn expand_dates<'a, I>(
+    calendar: Option<&Calendar>,
+    calendar_dates: I,
+) -> Result<BTreeSet<NaiveDate>>
+where
+    I: IntoIterator<Item = &'a CalendarDate>,
+{
+    let mut dates = BTreeSet::new();
+
+    if let Some(calendar) = calendar {
+        let start = parse_gtfs_date(&calendar.start_date)?;
+        let end = parse_gtfs_date(&calendar.end_date)?;
+
+        if end < start {
+            bail!("Calendar {} ends before it starts", calendar.service_id)
+        }
+
+        let mut day = start;
+        while day <= end {
+            if calendar.runs_on(day.weekday()) {
+                dates.insert(day);
+            }
+            day += Duration::days(1);
+        }
+    }
+
+    for calendar_date in calendar_dates {
+        let date = parse_gtfs_date(&calendar_date.date)?;
+        match calendar_date.exception_type {
+            SerivceExceptionType::Added => dates.insert(date),
+            SerivceExceptionType::Removed => dates.remove(&date),
+        };
+    }
+
+    Ok(dates)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+    use super::{compress_dates, expand_dates, CompressedCalendar, WEEKDAYS};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn days_between<F: Fn(&NaiveDate) -> bool>(
+        start: NaiveDate,
+        end: NaiveDate,
+        filter: F,
+    ) -> BTreeSet<NaiveDate> {
+        let mut result = BTreeSet::new();
+        let mut day = start;
+        while day <= end {
+            if filter(&day) {
+                result.insert(day);
+            }
+            day += Duration::days(1);
+        }
+        result
+    }
+
+    fn roundtrip(dates: &BTreeSet<NaiveDate>) -> CompressedCalendar {
+        let compressed = compress_dates("service", dates);
+        let expanded =
+            expand_dates(compressed.calendar.as_ref(), &compressed.calendar_dates).unwrap();
+        assert_eq!(&expanded, dates);
+        compressed
+    }
+
+    #[test]
+    fn test_empty() {
+        let compressed = roundtrip(&BTreeSet::new());
+        assert!(compressed.calendar.is_none());
+        assert!(compressed.calendar_dates.is_empty());
+    }
+
+    #[test]
+    fn test_single_date() {
+        let compressed = roundtrip(&BTreeSet::from([date(2023, 5, 1)]));
+        assert!(compressed.calendar.is_none());
+        assert_eq!(compressed.calendar_dates.len(), 1);
+    }
+
+    #[test]
+    fn test_workdays_with_holidays() {
+        let holidays = [date(2023, 12, 25), date(2023, 12, 26), date(2024, 1, 1)];
+        let dates = days_between(date(2023, 12, 1), date(2024, 1, 31), |day| {
+            day.weekday().num_days_from_monday() < 5 && !holidays.contains(day)
+        });
+
+        let compressed = roundtrip(&dates);
+        let calendar = compressed.calendar.unwrap();
+
+        for weekday in WEEKDAYS {
+            let expected = !matches!(weekday, Weekday::Sat | Weekday::Sun);
+            assert_eq!(calendar.runs_on(weekday), expected);
+        }
+        assert_eq!(compressed.calendar_dates.len(), holidays.len());
+    }
+
+    #[test]
+    fn test_weekends_with_added_holiday() {
+        let mut dates = days_between(date(2023, 4, 1), date(2023, 4, 30), |day| {
+            matches!(day.weekday(), Weekday::Sat | Weekday::Sun)
+        });
+        // Easter monday operates on the weekend schedule
+        dates.insert(date(2023, 4, 10));
+
+        let compressed = roundtrip(&dates);
+        assert!(compressed.calendar.is_some());
+        assert_eq!(compressed.calendar_dates.len(), 1);
+    }
+
+    #[test]
+    fn test_trailing_outlier_narrows_range() {
+        let mut dates = days_between(date(2023, 1, 2), date(2023, 3, 27), |day| {
+            day.weekday() == Weekday::Mon
+        });
+        dates.insert(date(2023, 6, 15));
+
+        let compressed = roundtrip(&dates);
+        let calendar = compressed.calendar.unwrap();
+
+        assert_eq!(calendar.end_date, "20230327");
+        assert_eq!(compressed.calendar_dates.len(), 1);
+    }
+
+    #[test]
+    fn test_sparse_dates() {
+        let dates = BTreeSet::from([date(2023, 1, 3), date(2023, 2, 17), date(2023, 3, 29)]);
+        let compressed = roundtrip(&dates);
+        assert!(compressed.calendar.is_none());
+        assert_eq!(compressed.calendar_dates.len(), 3);
+    }
+}
@@ -0,0 +1,250 @@
+/// Departure boards of a single stop built from the timetable
+///
+use std::collections::BTreeSet;
+
+use chrono::{Duration, NaiveDate};
+
+use super::headsigns::HeadsignSource;
+use super::{
+    calendar::expand_dates, format_gtfs_time, parse_gtfs_time, Calendar, CalendarDate,
+    GtfsFileType, Route, RowErrors, StopPickupType, StopTime, Trip,
+};
+use crate::hashing::{FastHashMap, FastHashSet};
+
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+
+#[derive(Debug)]
+pub struct Departure {
+    /// Seconds since midnight of the board date
+    pub time: u32,
+    pub route: String,
+    pub headsign: Option<String>,
+    pub trip_id: String,
+}
+
+/// Operating dates of every service, services with invalid calendars are skipped and counted
+fn service_dates<'a>(
+    calendars: &'a [Calendar],
+    calendar_dates: &'a [CalendarDate],
+    errors: &mut RowErrors,
+) -> FastHashMap<&'a str, BTreeSet<NaiveDate>> {
+    let mut exceptions: FastHashMap<&str, Vec<&CalendarDate>> = FastHashMap::default();
+    for calendar_date in calendar_dates {
+        exceptions
+            .entry(calendar_date.service_id.as_str())
+            .or_default()
+            .push(calendar_date);
+    }
+    let by_service: FastHashMap<&str, &Calendar> = calendars
+        .iter()
+        .map(|x| (x.service_id.as_str(), x))
+        .collect();
+
+    let service_ids: FastHashSet<&str> = by_service
+        .keys()
+        .chain(exceptions.keys())
+        .copied()
+        .collect();
+
+    let mut services = FastHashMap::default();
+    for service_id in service_ids {
+        let calendar = by_service.get(service_id).copied();
+        match expand_dates(
+            calendar,
+            exceptions.get(service_id).into_iter().flatten().copied(),
+        ) {
+            Ok(dates) => {
+                services.insert(service_id, dates);
+            }
+            Err(err) => errors.record_invalid(format!("service {service_id}: {err}")),
+        }
+    }
+
+    services
+}
+
+/// Departures from a stop between two times of a date, ordered by time
+///
+/// Times are seconds since midnight of the date, trips of the previous service day
+/// running past midnight are included. Stop times with invalid times and services
+/// with invalid calendars are skipped and counted per file.
+#[allow(clippy::too_many_arguments)]
+pub fn departure_board(
+    stop_id: &str,
+    date: NaiveDate,
+    from: u32,
+    to: u32,
+    routes: &[Route],
+    trips: &[Trip],
+    stop_times: &[StopTime],
+    calendars: &[Calendar],
+    calendar_dates: &[CalendarDate],
+) -> (Vec<Departure>, Vec<RowErrors>) {
+    let mut calendar_errors = RowErrors::new(GtfsFileType::Calendars);
+    let dates = service_dates(calendars, calendar_dates, &mut calendar_errors);
+
+    // Services of a day and its offset to the board date
+    let service_days = [(date - Duration::days(1), SECONDS_PER_DAY), (date, 0)];
+    let services: Vec<(FastHashSet<&str>, i64)> = service_days
+        .iter()
+        .map(|(day, offset)| {
+            let running = dates
+                .iter()
+                .filter(|(_, dates)| dates.contains(day))
+                .map(|(service_id, _)| *service_id)
+                .collect();
+            (running, *offset)
+        })
+        .collect();
+
+    let routes: FastHashMap<&str, &Route> =
+        routes.iter().map(|x| (x.route_id.as_str(), x)).collect();
+    let trips: FastHashMap<&str, &Trip> = trips.iter().map(|x| (x.trip_id.as_str(), x)).collect();
+
+    let mut board = Vec::new();
+    let mut errors = RowErrors::new(GtfsFileType::StopTimes);
+
+    for stop_time in stop_times {
+        if stop_time.stop_id.as_deref() != Some(stop_id)
+            || matches!(stop_time.pickup_type, Some(StopPickupType::NoPickup))
+        {
+            continue;
+        }
+        let Some(trip) = trips.get(stop_time.trip_id.as_str()) else {
+            continue;
+        };
+        let Some(departure_time) = &stop_time.departure_time else {
+            continue;
+        };
+        let departure_time = match parse_gtfs_time(departure_time) {
+            Ok(value) => value as i64,
+            Err(err) => {
+                errors.record_invalid(format!(
+                    "trip {} stop_sequence {}: {err}",
+                    stop_time.trip_id, stop_time.stop_sequence
+                ));
+                continue;
+            }
+        };
+
+        for (service_ids, offset) in &services {
+            if !service_ids.contains(trip.service_id.as_str()) {
+                continue;
+            }
+            let time = departure_time - offset;
+            if time < from as i64 || time > to as i64 {
+                continue;
+            }
+
+            let route = routes
+                .get(trip.route_id.as_str())
+                .and_then(|x| x.route_short_name.clone().or(x.route_long_name.clone()))
+                .unwrap_or_else(|| trip.route_id.clone());
+
+            board.push(Departure {
+                time: time as u32,
+                route,
+                headsign: stop_time
+                    .stop_headsign
+                    .clone()
+                    .or(trip.trip_headsign.clone()),
+                trip_id: trip.trip_id.clone(),
+            });
+        }
+    }
+
+    board.sort_by(|a, b| a.time.cmp(&b.time).then(a.route.cmp(&b.route)));
+    (board, vec![errors, calendar_errors])
+}
+
+/// Print a board, headsigns inferred by infer_headsigns are marked with a trailing *
+pub fn print_departure_board(board: &[Departure], inferred: &FastHashMap<String, HeadsignSource>) {
+    println!("{:<10} {:<20} {:<40} trip_id", "time", "route", "headsign");
+
+    for departure in board {
+        let mut headsign = departure.headsign.clone().unwrap_or_default();
+        if inferred.contains_key(&departure.trip_id) {
+            headsign.push('*');
+        }
+        println!(
+            "{:<10} {:<20} {:<40} {}",
+            format_gtfs_time(departure.time),
+            departure.route,
+            headsign,
+            departure.trip_id
+        );
+    }
+
+    if board.iter().any(|x| inferred.contains_key(&x.trip_id)) {
+        println!("* headsign inferred, missing in the feed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+
+    #[test]
+    fn test_departure_board() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 1,
+            ..Default::default()
+        });
+
+        // Late trip of the previous day shows up after midnight
+        let late_trip = feed.stop_times[0].trip_id.clone();
+        for stop_time in feed.stop_times.iter_mut() {
+            if stop_time.trip_id == late_trip {
+                let time = parse_gtfs_time(stop_time.departure_time.as_ref().unwrap()).unwrap();
+                let late = format_gtfs_time(time + SECONDS_PER_DAY as u32);
+                stop_time.arrival_time = Some(late.clone());
+                stop_time.departure_time = Some(late);
+            }
+        }
+        let stop_id = feed.stop_times[0].stop_id.clone().unwrap();
+        let departure = parse_gtfs_time(feed.stop_times[0].departure_time.as_ref().unwrap())
+            .unwrap()
+            - SECONDS_PER_DAY as u32;
+
+        // Tuesday after a regular workday
+        let date = NaiveDate::from_ymd_opt(2023, 3, 14).unwrap();
+        let board_of = |feed: &SyntheticFeed| {
+            departure_board(
+                &stop_id,
+                date,
+                0,
+                SECONDS_PER_DAY as u32,
+                &feed.routes,
+                &feed.trips,
+                &feed.stop_times,
+                &feed.calendars,
+                &feed.calendar_dates,
+            )
+        };
+        let (board, errors) = board_of(&feed);
+
+        assert!(errors.iter().all(|x| x.count == 0));
+        assert!(!board.is_empty());
+        assert!(board.windows(2).all(|x| x[0].time <= x[1].time));
+        assert!(board
+            .iter()
+            .any(|x| x.trip_id == late_trip && x.time == departure));
+        assert_eq!(board.iter().filter(|x| x.trip_id == late_trip).count(), 1);
+
+        // A malformed time drops only its own departure
+        feed.stop_times[0].departure_time = Some("late".to_string());
+        let (with_error, errors) = board_of(&feed);
+        assert_eq!(errors[0].count, 1);
+        assert_eq!(with_error.len(), board.len() - 1);
+
+        // A reversed calendar drops only its own service
+        let start = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2023, 2, 1).unwrap();
+        feed.calendars
+            .push(Calendar::weekly("reversed", &start, &end, [true; 7]));
+        let (with_calendar, errors) = board_of(&feed);
+        assert_eq!(errors[1].count, 1);
+        assert_eq!(with_calendar.len(), with_error.len());
+    }
+}
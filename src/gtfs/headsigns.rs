@@ -0,0 +1,135 @@
+/// Inference of trip headsigns missing in the feed
+///
+use super::{Stop, StopTime, Trip};
+use crate::hashing::FastHashMap;
+
+/// How an inferred headsign was derived
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HeadsignSource {
+    /// Most common headsign of trips serving the same stops
+    SamePattern,
+    /// Name of the last stop of the trip
+    LastStop,
+}
+
+/// Fill missing trip_headsign values, returning the source of every inferred value by trip_id
+pub fn infer_headsigns(
+    trips: &mut [Trip],
+    stops: &[Stop],
+    stop_times: &[StopTime],
+) -> FastHashMap<String, HeadsignSource> {
+    let mut by_trip: FastHashMap<&str, Vec<&StopTime>> = FastHashMap::default();
+    for stop_time in stop_times {
+        by_trip
+            .entry(stop_time.trip_id.as_str())
+            .or_default()
+            .push(stop_time);
+    }
+
+    let patterns: FastHashMap<&str, Vec<&str>> = by_trip
+        .into_iter()
+        .map(|(trip_id, mut trip_stop_times)| {
+            trip_stop_times.sort_by_key(|x| x.stop_sequence);
+            let pattern = trip_stop_times
+                .iter()
+                .map(|x| x.stop_id.as_deref().unwrap_or_default())
+                .collect();
+            (trip_id, pattern)
+        })
+        .collect();
+
+    let mut pattern_headsigns: FastHashMap<&[&str], FastHashMap<&str, usize>> =
+        FastHashMap::default();
+    for trip in trips.iter() {
+        let (Some(headsign), Some(pattern)) =
+            (&trip.trip_headsign, patterns.get(trip.trip_id.as_str()))
+        else {
+            continue;
+        };
+        *pattern_headsigns
+            .entry(pattern.as_slice())
+            .or_default()
+            .entry(headsign.as_str())
+            .or_default() += 1;
+    }
+
+    // Ties are broken by the headsign text to keep results reproducible
+    let most_common: FastHashMap<&[&str], String> = pattern_headsigns
+        .into_iter()
+        .filter_map(|(pattern, counts)| {
+            let (headsign, _) = counts
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))?;
+            Some((pattern, headsign.to_string()))
+        })
+        .collect();
+
+    let stop_names: FastHashMap<&str, &str> = stops
+        .iter()
+        .filter_map(|x| Some((x.stop_id.as_str(), x.stop_name.as_deref()?)))
+        .collect();
+
+    let mut inferred = FastHashMap::default();
+
+    for trip in trips.iter_mut() {
+        if trip.trip_headsign.is_some() {
+            continue;
+        }
+        let Some(pattern) = patterns.get(trip.trip_id.as_str()) else {
+            continue;
+        };
+
+        let (headsign, source) = if let Some(headsign) = most_common.get(pattern.as_slice()) {
+            (headsign.clone(), HeadsignSource::SamePattern)
+        } else if let Some(name) = pattern.last().and_then(|x| stop_names.get(x)) {
+            (name.to_string(), HeadsignSource::LastStop)
+        } else {
+            continue;
+        };
+
+        trip.trip_headsign = Some(headsign);
+        inferred.insert(trip.trip_id.clone(), source);
+    }
+
+    inferred
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+
+    use super::{infer_headsigns, HeadsignSource};
+
+    #[test]
+    fn test_infer_headsigns() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 2,
+            variants_per_route: 1,
+            trips_per_variant: 3,
+            ..Default::default()
+        });
+
+        // Route 0 keeps one trip with a headsign, route 1 has none at all
+        feed.trips[0].trip_headsign = Some("Center".to_string());
+        for trip in feed.trips.iter_mut().skip(1) {
+            trip.trip_headsign = None;
+        }
+
+        let inferred = infer_headsigns(&mut feed.trips, &feed.stops, &feed.stop_times);
+
+        assert_eq!(inferred.len(), 5);
+        assert_eq!(
+            inferred["route-0-variant-0-trip-1"],
+            HeadsignSource::SamePattern
+        );
+        assert_eq!(feed.trips[1].trip_headsign.as_deref(), Some("Center"));
+        assert_eq!(
+            inferred["route-1-variant-0-trip-0"],
+            HeadsignSource::LastStop
+        );
+        assert_eq!(
+            feed.trips[3].trip_headsign.as_deref(),
+            Some("Route 1 stop 19")
+        );
+    }
+}
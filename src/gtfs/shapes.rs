@@ -0,0 +1,280 @@
+/// Snapping of stops onto trip shapes
+///
+use super::{Shape, Stop, StopTime, Trip};
+use crate::geo::{cumulative_distances, project_onto_polyline, Point, Projection};
+use crate::hashing::FastHashMap;
+
+/// Shape points ordered by sequence with distances along the shape
+pub struct ShapeLine {
+    pub points: Vec<Point>,
+    pub cumulative_m: Vec<f64>,
+    /// shape_dist_traveled of every point, if the feed provides it for all of them
+    pub dist_traveled: Option<Vec<f64>>,
+}
+
+impl ShapeLine {
+    /// Distance along the shape in units of the feed, meters if the feed has none
+    pub fn dist_traveled(&self, projection: &Projection) -> f64 {
+        let Some(dist_traveled) = &self.dist_traveled else {
+            return projection.distance_along_m;
+        };
+
+        let segment = projection.segment;
+        if segment + 1 >= dist_traveled.len() {
+            return dist_traveled[segment];
+        }
+
+        let length = self.cumulative_m[segment + 1] - self.cumulative_m[segment];
+        let t = if length > 0.0 {
+            (projection.distance_along_m - self.cumulative_m[segment]) / length
+        } else {
+            0.0
+        };
+
+        dist_traveled[segment] + (dist_traveled[segment + 1] - dist_traveled[segment]) * t
+    }
+
+    /// Project ordered stop locations onto the shape, keeping projections monotonic
+    pub fn snap(&self, stops: &[Option<Point>]) -> Vec<Option<Projection>> {
+        let mut from_segment = 0;
+
+        stops
+            .iter()
+            .map(|stop| {
+                let projection = project_onto_polyline(
+                    stop.as_ref()?,
+                    &self.points,
+                    &self.cumulative_m,
+                    from_segment,
+                )?;
+                from_segment = projection.segment;
+                Some(projection)
+            })
+            .collect()
+    }
+}
+
+fn shape_points_by_id(shapes: &[Shape]) -> FastHashMap<&str, Vec<&Shape>> {
+    let mut by_id: FastHashMap<&str, Vec<&Shape>> = FastHashMap::default();
+
+    for shape in shapes {
+        by_id
+            .entry(shape.shape_id.as_str())
+            .or_default()
+            .push(shape);
+    }
+
+    for points in by_id.values_mut() {
+        points.sort_by_key(|x| x.shape_pt_sequence);
+    }
+
+    by_id
+}
+
+fn points_dist_traveled(points: &[&Shape]) -> Option<Vec<f64>> {
+    points.iter().map(|x| x.shape_dist_traveled).collect()
+}
+
+/// Build polylines of all shapes
+pub fn shape_lines(shapes: &[Shape]) -> FastHashMap<&str, ShapeLine> {
+    shape_points_by_id(shapes)
+        .into_iter()
+        .map(|(shape_id, shape_points)| {
+            let points: Vec<Point> = shape_points
+                .iter()
+                .map(|x| Point::new(x.shape_pt_lat, x.shape_pt_lon))
+                .collect();
+            let cumulative_m = cumulative_distances(&points);
+            let dist_traveled = points_dist_traveled(&shape_points);
+            (
+                shape_id,
+                ShapeLine {
+                    points,
+                    cumulative_m,
+                    dist_traveled,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Fill shape_dist_traveled in meters for shapes that have no distances at all
+///
+/// Shapes with partially filled distances are left untouched, since the unit
+/// of existing values is not known.
+pub fn fill_shape_dist_traveled(shapes: &mut [Shape]) {
+    // Distance by shape_pt_sequence of every shape without distances
+    let mut distances: FastHashMap<String, FastHashMap<u64, f64>> = FastHashMap::default();
+
+    for (shape_id, points) in shape_points_by_id(shapes) {
+        if points.iter().any(|x| x.shape_dist_traveled.is_some()) {
+            continue;
+        }
+        let line: Vec<Point> = points
+            .iter()
+            .map(|x| Point::new(x.shape_pt_lat, x.shape_pt_lon))
+            .collect();
+        let by_sequence = distances.entry(shape_id.to_string()).or_default();
+        for (point, distance) in points.iter().zip(cumulative_distances(&line)) {
+            // The first of duplicated sequences wins
+            by_sequence
+                .entry(point.shape_pt_sequence)
+                .or_insert(distance);
+        }
+    }
+
+    for shape in shapes.iter_mut() {
+        let Some(shape_distances) = distances.get(&shape.shape_id) else {
+            continue;
+        };
+        shape.shape_dist_traveled = shape_distances.get(&shape.shape_pt_sequence).copied();
+    }
+}
+
+/// Stop times of one trip snapped onto the trip shape
+pub struct SnappedTrip<'a> {
+    pub trip: &'a Trip,
+    /// Stop times ordered by stop_sequence with their projection onto the shape
+    pub stops: Vec<(&'a StopTime, Option<Projection>)>,
+}
+
+/// Snap stops of every trip having a shape onto that shape
+pub fn snap_trips<'a>(
+    trips: &'a [Trip],
+    stops: &'a [Stop],
+    stop_times: &'a [StopTime],
+    lines: &FastHashMap<&str, ShapeLine>,
+) -> Vec<SnappedTrip<'a>> {
+    let points: FastHashMap<&str, Point> = stops
+        .iter()
+        .filter_map(|stop| {
+            Some((
+                stop.stop_id.as_str(),
+                Point::new(stop.stop_lat?, stop.stop_lon?),
+            ))
+        })
+        .collect();
+
+    let mut by_trip: FastHashMap<&str, Vec<&StopTime>> = FastHashMap::default();
+    for stop_time in stop_times {
+        by_trip
+            .entry(stop_time.trip_id.as_str())
+            .or_default()
+            .push(stop_time);
+    }
+
+    let mut result = Vec::new();
+
+    for trip in trips {
+        let Some(line) = trip.shape_id.as_ref().and_then(|x| lines.get(x.as_str())) else {
+            continue;
+        };
+        let Some(trip_stop_times) = by_trip.get_mut(trip.trip_id.as_str()) else {
+            continue;
+        };
+        trip_stop_times.sort_by_key(|x| x.stop_sequence);
+
+        let locations: Vec<Option<Point>> = trip_stop_times
+            .iter()
+            .map(|x| x.stop_id.as_deref().and_then(|id| points.get(id)).copied())
+            .collect();
+
+        result.push(SnappedTrip {
+            trip,
+            stops: trip_stop_times
+                .iter()
+                .copied()
+                .zip(line.snap(&locations))
+                .collect(),
+        });
+    }
+
+    result
+}
+
+/// Fill missing stop_times shape_dist_traveled with distances along the trip shape
+///
+/// Distances are interpolated from the shape's own shape_dist_traveled when present,
+/// so filled values use the same units as the rest of the feed.
+pub fn fill_stop_times_dist_traveled(
+    trips: &[Trip],
+    stops: &[Stop],
+    shapes: &[Shape],
+    stop_times: &mut [StopTime],
+) {
+    let lines = shape_lines(shapes);
+
+    let mut distances: FastHashMap<(String, u64), f64> = FastHashMap::default();
+
+    for snapped in snap_trips(trips, stops, stop_times, &lines) {
+        let Some(line) = snapped
+            .trip
+            .shape_id
+            .as_ref()
+            .and_then(|x| lines.get(x.as_str()))
+        else {
+            continue;
+        };
+        for (stop_time, projection) in snapped.stops {
+            if let Some(projection) = projection {
+                distances.insert(
+                    (stop_time.trip_id.clone(), stop_time.stop_sequence),
+                    line.dist_traveled(&projection),
+                );
+            }
+        }
+    }
+
+    for stop_time in stop_times.iter_mut() {
+        if stop_time.shape_dist_traveled.is_some() {
+            continue;
+        }
+        stop_time.shape_dist_traveled = distances
+            .get(&(stop_time.trip_id.clone(), stop_time.stop_sequence))
+            .copied();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+    use crate::gtfs::Shape;
+
+    use super::{fill_shape_dist_traveled, fill_stop_times_dist_traveled};
+
+    #[test]
+    fn test_fill_distances() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 1,
+            stops_per_route: 5,
+            variants_per_route: 1,
+            trips_per_variant: 1,
+            ..Default::default()
+        });
+
+        // Shape follows the stops of the only trip
+        let mut shapes: Vec<Shape> = feed
+            .stops
+            .iter()
+            .enumerate()
+            .map(|(i, stop)| Shape {
+                shape_id: "shape".to_string(),
+                shape_pt_lat: stop.stop_lat.unwrap(),
+                shape_pt_lon: stop.stop_lon.unwrap(),
+                shape_pt_sequence: i as u64,
+                shape_dist_traveled: None,
+            })
+            .collect();
+        feed.trips[0].shape_id = Some("shape".to_string());
+
+        fill_shape_dist_traveled(&mut shapes);
+        fill_stop_times_dist_traveled(&feed.trips, &feed.stops, &shapes, &mut feed.stop_times);
+
+        for (shape, stop_time) in shapes.iter().zip(&feed.stop_times) {
+            let expected = shape.shape_dist_traveled.unwrap();
+            let filled = stop_time.shape_dist_traveled.unwrap();
+            assert!((expected - filled).abs() < 1.0, "{expected} != {filled}");
+        }
+        assert_eq!(shapes[0].shape_dist_traveled, Some(0.0));
+    }
+}
@@ -0,0 +1,210 @@
+/// Linking gtfs stops to external station registries by proximity and name
+///
+use std::io::BufRead;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::Stop;
+use crate::csv::CsvTableReader;
+use crate::geo::{haversine_m, Point};
+use crate::hashing::FastHashMap;
+
+const METERS_PER_DEGREE_LAT: f64 = 111_320.0;
+
+/// Station of an external registry, read from a csv with id, name, lat and lon columns
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalStation {
+    pub id: String,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+pub fn read_stations<R: BufRead>(read: R) -> Result<Vec<ExternalStation>> {
+    let mut reader = CsvTableReader::new(read)?;
+    let mut buf = String::new();
+    let mut field_buf = Vec::new();
+    let mut stations = Vec::new();
+
+    while let Some(station) = reader.read::<ExternalStation>(&mut field_buf, &mut buf)? {
+        stations.push(station);
+    }
+
+    Ok(stations)
+}
+
+pub struct MatchParams {
+    /// Stations further away than this are never matched
+    pub max_distance_m: f64,
+    /// Matches with lower confidence are dropped
+    pub min_confidence: f64,
+}
+
+impl Default for MatchParams {
+    fn default() -> Self {
+        MatchParams {
+            max_distance_m: 300.0,
+            min_confidence: 0.5,
+        }
+    }
+}
+
+/// Link between a gtfs stop and an external station
+#[derive(Debug, Serialize)]
+pub struct StopMatch {
+    pub stop_id: String,
+    pub external_id: String,
+    pub distance_m: f64,
+    /// Normalized levenshtein similarity of the names, 1 for identical names
+    pub name_similarity: f64,
+    /// Average of name similarity and closeness relative to the maximum distance
+    pub confidence: f64,
+}
+
+/// Lowercase words of a name without punctuation, "St. Pauli-Bf" becomes "st pauli bf"
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Stations bucketed into cells of roughly max_distance_m on each side
+struct StationGrid<'a> {
+    cell_deg: f64,
+    cells: FastHashMap<(i64, i64), Vec<&'a ExternalStation>>,
+}
+
+impl<'a> StationGrid<'a> {
+    fn new(stations: &'a [ExternalStation], max_distance_m: f64) -> Self {
+        let mut grid = StationGrid {
+            cell_deg: max_distance_m / METERS_PER_DEGREE_LAT,
+            cells: FastHashMap::default(),
+        };
+        for station in stations {
+            let cell = grid.cell(station.lat, station.lon);
+            grid.cells.entry(cell).or_default().push(station);
+        }
+        grid
+    }
+
+    fn cell(&self, lat: f64, lon: f64) -> (i64, i64) {
+        (
+            (lat / self.cell_deg).floor() as i64,
+            (lon / self.cell_deg).floor() as i64,
+        )
+    }
+
+    /// Stations in cells that may lie within max_distance_m of the point
+    fn near(&self, point: &Point) -> impl Iterator<Item = &'a ExternalStation> + '_ {
+        let (lat_cell, lon_cell) = self.cell(point.lat, point.lon);
+        // Degrees of longitude shrink towards the poles
+        let lon_span = (1.0 / point.lat.to_radians().cos().max(0.01)).ceil() as i64;
+
+        (lat_cell - 1..=lat_cell + 1)
+            .flat_map(move |lat| {
+                (lon_cell - lon_span..=lon_cell + lon_span).map(move |lon| (lat, lon))
+            })
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+/// Match every named stop with coordinates to the most likely external station
+#[tracing::instrument(
+    skip_all,
+    fields(stops = stops.len(), stations = stations.len(), matches = tracing::field::Empty)
+)]
+pub fn match_stops(
+    stops: &[Stop],
+    stations: &[ExternalStation],
+    params: &MatchParams,
+) -> Vec<StopMatch> {
+    let grid = StationGrid::new(stations, params.max_distance_m);
+    let mut matches = Vec::new();
+
+    for stop in stops {
+        let (Some(lat), Some(lon), Some(name)) = (stop.stop_lat, stop.stop_lon, &stop.stop_name)
+        else {
+            continue;
+        };
+        let point = Point::new(lat, lon);
+        if !point.is_valid() || point.is_placeholder() {
+            continue;
+        }
+        let name = normalize_name(name);
+
+        let best = grid
+            .near(&point)
+            .filter_map(|station| {
+                let distance_m = haversine_m(&point, &Point::new(station.lat, station.lon));
+                if distance_m > params.max_distance_m {
+                    return None;
+                }
+                let name_similarity =
+                    strsim::normalized_levenshtein(&name, &normalize_name(&station.name));
+                let closeness = 1.0 - distance_m / params.max_distance_m;
+                let confidence = (name_similarity + closeness) / 2.0;
+
+                Some(StopMatch {
+                    stop_id: stop.stop_id.clone(),
+                    external_id: station.id.clone(),
+                    distance_m,
+                    name_similarity,
+                    confidence,
+                })
+            })
+            .max_by(|a, b| a.confidence.total_cmp(&b.confidence));
+
+        if let Some(best) = best {
+            if best.confidence >= params.min_confidence {
+                matches.push(best);
+            }
+        }
+    }
+
+    tracing::Span::current().record("matches", matches.len());
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("St. Pauli-Bf "), "st pauli bf");
+    }
+
+    #[test]
+    fn test_match_stops() {
+        let feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 1,
+            ..Default::default()
+        });
+        let stop = &feed.stops[0];
+        let (lat, lon) = (stop.stop_lat.unwrap(), stop.stop_lon.unwrap());
+
+        let text = format!(
+            "id,name,lat,lon\n\
+             near-same-name,{name},{lat},{lon_near}\n\
+             near-other-name,Central,{lat},{lon}\n\
+             far-same-name,{name},{lat_far},{lon}\n",
+            name = stop.stop_name.as_ref().unwrap(),
+            lon_near = lon + 0.0005,
+            lat_far = lat + 0.01,
+        );
+        let stations = read_stations(Cursor::new(text)).unwrap();
+
+        let matches = match_stops(&feed.stops[..1], &stations, &MatchParams::default());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].external_id, "near-same-name");
+        assert_eq!(matches[0].name_similarity, 1.0);
+    }
+}
@@ -0,0 +1,339 @@
+/// Generator of synthetic gtfs feeds for tests and benchmarks
+///
+use std::io::{BufRead, Cursor};
+
+use chrono::{Datelike, Duration, NaiveDate};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    calendar::format_gtfs_date, format_gtfs_time, Agency, Calendar, CalendarDate, Color,
+    GtfsFileType, GtfsStore, Route, RouteType, SerivceExceptionType, Stop, StopTime, Trip,
+    TripDirection,
+};
+use crate::csv::to_csv_text;
+
+pub struct SyntheticFeedParams {
+    pub routes: usize,
+    pub stops_per_route: usize,
+    /// Number of stop patterns per route, each derived from the base pattern
+    pub variants_per_route: usize,
+    pub trips_per_variant: usize,
+    /// Maximum number of stop insertions and deletions applied to a variant
+    pub max_edits: usize,
+    /// Maximum random deviation of stop times in seconds
+    pub time_noise_secs: u32,
+    pub start_date: NaiveDate,
+    pub days: u32,
+    pub seed: u64,
+}
+
+impl Default for SyntheticFeedParams {
+    fn default() -> Self {
+        SyntheticFeedParams {
+            routes: 10,
+            stops_per_route: 20,
+            variants_per_route: 3,
+            trips_per_variant: 20,
+            max_edits: 2,
+            time_noise_secs: 0,
+            start_date: NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
+            days: 365,
+            seed: 0,
+        }
+    }
+}
+
+/// Generated feed, readable as a gtfs store
+pub struct SyntheticFeed {
+    pub agencies: Vec<Agency>,
+    pub stops: Vec<Stop>,
+    pub routes: Vec<Route>,
+    pub trips: Vec<Trip>,
+    pub stop_times: Vec<StopTime>,
+    pub calendars: Vec<Calendar>,
+    pub calendar_dates: Vec<CalendarDate>,
+}
+
+const AGENCY_ID: &str = "synthetic";
+const WORKDAY_SERVICE: &str = "workdays";
+const WEEKEND_SERVICE: &str = "weekends";
+
+fn make_stop(stop_id: String, name: String, lat: f64, lon: f64) -> Stop {
+    Stop {
+        stop_id,
+        stop_code: None,
+        stop_name: Some(name),
+        stop_desc: None,
+        stop_lat: Some(lat),
+        stop_lon: Some(lon),
+        zone_id: None,
+        stop_url: None,
+        location_type: None,
+        parent_station: None,
+        stop_timezone: None,
+        wheelchair_boarding: None,
+        level_id: None,
+        platform_code: None,
+    }
+}
+
+fn make_calendars(params: &SyntheticFeedParams) -> (Vec<Calendar>, Vec<CalendarDate>) {
+    let end_date = params.start_date + Duration::days(params.days.saturating_sub(1) as i64);
+
+    let workdays = [true, true, true, true, true, false, false];
+    let weekends = workdays.map(|x| !x);
+
+    let calendars = vec![
+        Calendar::weekly(WORKDAY_SERVICE, &params.start_date, &end_date, workdays),
+        Calendar::weekly(WEEKEND_SERVICE, &params.start_date, &end_date, weekends),
+    ];
+
+    // Holidays on the first workday of every month run on the weekend schedule
+    let mut calendar_dates = Vec::new();
+    let mut day = params.start_date;
+    while day <= end_date {
+        if day.day() <= 7 && day.weekday().num_days_from_monday() < 5 {
+            let date = format_gtfs_date(&day);
+            calendar_dates.push(CalendarDate {
+                service_id: WORKDAY_SERVICE.to_string(),
+                date: date.clone(),
+                exception_type: SerivceExceptionType::Removed,
+            });
+            calendar_dates.push(CalendarDate {
+                service_id: WEEKEND_SERVICE.to_string(),
+                date,
+                exception_type: SerivceExceptionType::Added,
+            });
+            day = NaiveDate::from_ymd_opt(day.year(), day.month(), 8).unwrap();
+            continue;
+        }
+        day += Duration::days(1);
+    }
+
+    (calendars, calendar_dates)
+}
+
+impl SyntheticFeed {
+    pub fn generate(params: &SyntheticFeedParams) -> Self {
+        let mut rng = StdRng::seed_from_u64(params.seed);
+
+        let (calendars, calendar_dates) = make_calendars(params);
+
+        let mut feed = SyntheticFeed {
+            agencies: vec![Agency {
+                agency_id: AGENCY_ID.to_string(),
+                agency_name: "Synthetic transit".to_string(),
+                agency_url: "https://example.com".to_string(),
+                agency_timezone: "Europe/Berlin".to_string(),
+                agency_lang: None,
+                agency_phone: None,
+                agency_fare_url: None,
+                agency_email: None,
+                ticketing_deep_link_id: None,
+            }],
+            stops: Vec::new(),
+            routes: Vec::new(),
+            trips: Vec::new(),
+            stop_times: Vec::new(),
+            calendars,
+            calendar_dates,
+        };
+
+        for route_i in 0..params.routes {
+            let route_id = format!("route-{route_i}");
+
+            let mut route = Route::simple(AGENCY_ID, &format!("R{route_i}"));
+            route.route_id = route_id.clone();
+            route.route_type = RouteType::Bus;
+            route.route_color = Some(Color::parse("1F4E9C"));
+            route.route_text_color = Some(Color::parse("FFFFFF"));
+            feed.routes.push(route);
+
+            // Routes fan out from a common center
+            let bearing = route_i as f64 * 2.399;
+            let (lat_step, lon_step) = (bearing.sin() * 0.005, bearing.cos() * 0.008);
+
+            let mut base_pattern = Vec::with_capacity(params.stops_per_route);
+            for stop_i in 0..params.stops_per_route {
+                let stop_id = format!("{route_id}-stop-{stop_i}");
+                feed.stops.push(make_stop(
+                    stop_id.clone(),
+                    format!("Route {route_i} stop {stop_i}"),
+                    52.5 + lat_step * stop_i as f64,
+                    13.4 + lon_step * stop_i as f64,
+                ));
+                base_pattern.push(stop_id);
+            }
+
+            for variant_i in 0..params.variants_per_route {
+                let mut pattern = base_pattern.clone();
+
+                if variant_i > 0 && params.max_edits > 0 {
+                    let edits = rng.gen_range(1..=params.max_edits);
+                    for edit_i in 0..edits {
+                        if pattern.len() > 2 && rng.gen_bool(0.5) {
+                            pattern.remove(rng.gen_range(1..pattern.len() - 1));
+                        } else {
+                            let stop_id = format!("{route_id}-variant-{variant_i}-extra-{edit_i}");
+                            feed.stops.push(make_stop(
+                                stop_id.clone(),
+                                format!("Route {route_i} variant {variant_i} extra {edit_i}"),
+                                52.5 + lat_step * (rng.gen::<f64>() * 4.0 - 2.0),
+                                13.4 + lon_step * (rng.gen::<f64>() * 4.0 - 2.0),
+                            ));
+                            pattern.insert(rng.gen_range(1..pattern.len()), stop_id);
+                        }
+                    }
+                }
+
+                let hop_seconds: Vec<u32> = (1..pattern.len())
+                    .map(|_| rng.gen_range(90..=300))
+                    .collect();
+
+                for trip_i in 0..params.trips_per_variant {
+                    let trip_id = format!("{route_id}-variant-{variant_i}-trip-{trip_i}");
+                    let service_id = if trip_i % 3 == 2 {
+                        WEEKEND_SERVICE
+                    } else {
+                        WORKDAY_SERVICE
+                    };
+
+                    feed.trips.push(Trip {
+                        route_id: route_id.clone(),
+                        service_id: service_id.to_string(),
+                        trip_id: trip_id.clone(),
+                        trip_headsign: pattern.last().cloned(),
+                        trip_short_name: None,
+                        direction_id: Some(TripDirection::Outbound),
+                        block_id: None,
+                        shape_id: None,
+                        wheelchair_accessible: None,
+                        bikes_allowed: None,
+                        trip_ticketing_id: None,
+                        ticketing_type: None,
+                    });
+
+                    let mut time = 5 * 3600 + (trip_i as u32) * 20 * 60 + (variant_i as u32) * 60;
+                    for (stop_i, stop_id) in pattern.iter().enumerate() {
+                        if stop_i > 0 {
+                            time += hop_seconds[stop_i - 1];
+                        }
+                        let noise = if params.time_noise_secs > 0 {
+                            rng.gen_range(0..=params.time_noise_secs)
+                        } else {
+                            0
+                        };
+                        let stop_time = format_gtfs_time(time + noise);
+
+                        feed.stop_times.push(StopTime {
+                            trip_id: trip_id.clone(),
+                            arrival_time: Some(stop_time.clone()),
+                            departure_time: Some(stop_time),
+                            stop_id: Some(stop_id.clone()),
+                            stop_sequence: stop_i as u64 + 1,
+                            stop_headsign: None,
+                            pickup_type: None,
+                            drop_off_type: None,
+                            continuous_pickup: None,
+                            continuous_drop_off: None,
+                            shape_dist_traveled: None,
+                            timepoint: None,
+                            ticketing_type: None,
+                            location_group_id: None,
+                            location_id: None,
+                            start_pickup_drop_off_window: None,
+                            end_pickup_drop_off_window: None,
+                            pickup_booking_rule_id: None,
+                            drop_off_booking_rule_id: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        feed
+    }
+}
+
+impl GtfsStore for SyntheticFeed {
+    fn get_readable<'a>(&'a mut self, file_type: GtfsFileType) -> Option<Box<dyn BufRead + 'a>> {
+        use GtfsFileType::*;
+
+        let text = match file_type {
+            Agencies => to_csv_text(&self.agencies),
+            Stops => to_csv_text(&self.stops),
+            Routes => to_csv_text(&self.routes),
+            Trips => to_csv_text(&self.trips),
+            StopTimes => to_csv_text(&self.stop_times),
+            Calendars => to_csv_text(&self.calendars),
+            CalendarDates => to_csv_text(&self.calendar_dates),
+            _ => None,
+        }?;
+
+        Some(Box::new(Cursor::new(text)))
+    }
+
+    fn has_file(&mut self, file_type: GtfsFileType) -> bool {
+        use GtfsFileType::*;
+
+        match file_type {
+            Agencies => !self.agencies.is_empty(),
+            Stops => !self.stops.is_empty(),
+            Routes => !self.routes.is_empty(),
+            Trips => !self.trips.is_empty(),
+            StopTimes => !self.stop_times.is_empty(),
+            Calendars => !self.calendars.is_empty(),
+            CalendarDates => !self.calendar_dates.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv::CsvTableReader;
+    use crate::gtfs::{GtfsFileType, GtfsStore, StopTime};
+
+    use super::{SyntheticFeed, SyntheticFeedParams};
+
+    #[test]
+    fn test_generated_feed_is_readable() {
+        let params = SyntheticFeedParams {
+            routes: 3,
+            stops_per_route: 5,
+            variants_per_route: 2,
+            trips_per_variant: 4,
+            time_noise_secs: 30,
+            ..Default::default()
+        };
+        let mut feed = SyntheticFeed::generate(&params);
+
+        assert_eq!(feed.trips.len(), 3 * 2 * 4);
+
+        let expected = feed.stop_times.len();
+        let readable = feed.get_readable(GtfsFileType::StopTimes).unwrap();
+        let mut reader = CsvTableReader::new(readable).unwrap();
+
+        let mut buf = String::new();
+        let mut field_buf = Vec::new();
+        let mut count = 0;
+        while let Some(stop_time) = reader.read::<StopTime>(&mut field_buf, &mut buf).unwrap() {
+            assert!(stop_time.arrival_time.is_some());
+            count += 1;
+        }
+
+        assert_eq!(count, expected);
+    }
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        let params = SyntheticFeedParams::default();
+        let first = SyntheticFeed::generate(&params);
+        let second = SyntheticFeed::generate(&params);
+
+        let stops = |feed: &SyntheticFeed| -> Vec<Option<String>> {
+            feed.stop_times.iter().map(|x| x.stop_id.clone()).collect()
+        };
+        assert_eq!(stops(&first), stops(&second));
+    }
+}
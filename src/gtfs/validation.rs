@@ -0,0 +1,1237 @@
+/// Consistency checks of a gtfs feed
+///
+use std::{collections::BTreeSet, fmt};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::de::DeserializeOwned;
+
+use super::{
+    calendar::{expand_dates, parse_gtfs_date},
+    parse_gtfs_time,
+    shapes::{shape_lines, snap_trips},
+    Agency, BookingRule, Calendar, CalendarDate, Color, FeedInfo, Location, LocationGroup,
+    LocationGroupStop, Route, RouteType, SerivceExceptionType, Shape, Stop, StopTime,
+    TimePointType, Trip,
+};
+use crate::geo::{haversine_m, median_point, Point};
+use crate::gtfs::{GtfsFile, GtfsStore, RowErrors};
+use crate::hashing::{FastHashMap, FastHashSet};
+
+use rules::{default_severity, RuleSet, Severity};
+
+pub mod rules;
+pub mod score;
+
+/// Number of offending records kept as examples per check
+const MAX_SAMPLES: usize = 5;
+
+/// Consecutive stops closer than this are considered to be at the same place
+const ZERO_DISTANCE_M: f64 = 1.0;
+
+/// Lowest contrast between route color and text color, WCAG minimum for large text
+const MIN_COLOR_CONTRAST: f64 = 3.0;
+
+/// Stops further away from the center of the feed are considered misplaced
+const MAX_DISTANCE_FROM_FEED_M: f64 = 1_000_000.0;
+
+/// Stops further away from the trip shape are considered misplaced
+const MAX_SHAPE_OFFSET_M: f64 = 100.0;
+
+/// Stop times are often rounded to minutes, shorter hops are assumed to take a minute
+const MIN_HOP_SECONDS: u32 = 60;
+
+/// Violations of a single check
+#[derive(Debug)]
+pub struct Finding {
+    pub check: &'static str,
+    pub severity: Severity,
+    pub count: usize,
+    pub samples: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    /// Register violation of a check
+    pub fn record(&mut self, check: &'static str, sample: String) {
+        let finding = match self.findings.iter_mut().position(|x| x.check == check) {
+            Some(position) => &mut self.findings[position],
+            None => {
+                self.findings.push(Finding {
+                    check,
+                    severity: default_severity(check),
+                    count: 0,
+                    samples: Vec::new(),
+                });
+                self.findings.last_mut().unwrap()
+            }
+        };
+
+        finding.count += 1;
+        if finding.samples.len() < MAX_SAMPLES {
+            finding.samples.push(sample);
+        }
+    }
+
+    /// Drop disabled rules and apply severity overrides
+    pub fn apply_rules(&mut self, rules: &RuleSet) {
+        self.findings
+            .retain_mut(|finding| match rules.severity(finding.check) {
+                Some(severity) => {
+                    finding.severity = severity;
+                    true
+                }
+                None => false,
+            });
+    }
+
+    /// Highest severity among the findings
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|x| x.severity).max()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn print(&self) {
+        print!("{self}");
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.findings.is_empty() {
+            return writeln!(f, "No issues found");
+        }
+
+        for finding in &self.findings {
+            writeln!(
+                f,
+                "[{}] {}: {} violations",
+                finding.severity, finding.check, finding.count
+            )?;
+            for sample in &finding.samples {
+                writeln!(f, "    {sample}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tables required by the checks
+pub struct ValidationInput {
+    pub agencies: Vec<Agency>,
+    pub routes: Vec<Route>,
+    pub trips: Vec<Trip>,
+    pub stops: Vec<Stop>,
+    pub stop_times: Vec<StopTime>,
+    pub shapes: Vec<Shape>,
+    pub calendars: Vec<Calendar>,
+    pub calendar_dates: Vec<CalendarDate>,
+    pub feed_info: Vec<FeedInfo>,
+    pub location_groups: Vec<LocationGroup>,
+    pub location_group_stops: Vec<LocationGroupStop>,
+    pub booking_rules: Vec<BookingRule>,
+    pub locations: Vec<Location>,
+    pub row_errors: Vec<RowErrors>,
+}
+
+/// Read a table keeping rows which could not be parsed for the report
+fn read_table<I: DeserializeOwned + GtfsFile, S: GtfsStore>(
+    store: &mut S,
+    optional: bool,
+    row_errors: &mut Vec<RowErrors>,
+) -> Result<Vec<I>> {
+    if optional && !store.has_file(I::get_file_type()) {
+        return Ok(Vec::new());
+    }
+
+    let (items, errors) = store.scan_all()?;
+    if errors.count > 0 {
+        row_errors.push(errors);
+    }
+    Ok(items)
+}
+
+impl ValidationInput {
+    pub fn from_store<S: GtfsStore>(store: &mut S) -> Result<Self> {
+        let mut row_errors = Vec::new();
+
+        Ok(ValidationInput {
+            agencies: read_table(store, false, &mut row_errors)?,
+            routes: read_table(store, false, &mut row_errors)?,
+            trips: read_table(store, false, &mut row_errors)?,
+            stops: read_table(store, false, &mut row_errors)?,
+            stop_times: read_table(store, false, &mut row_errors)?,
+            shapes: read_table(store, true, &mut row_errors)?,
+            calendars: read_table(store, true, &mut row_errors)?,
+            calendar_dates: read_table(store, true, &mut row_errors)?,
+            feed_info: read_table(store, true, &mut row_errors)?,
+            location_groups: read_table(store, true, &mut row_errors)?,
+            location_group_stops: read_table(store, true, &mut row_errors)?,
+            booking_rules: read_table(store, true, &mut row_errors)?,
+            locations: store.read_locations()?,
+            row_errors,
+        })
+    }
+
+    /// Stop times of every trip ordered by stop_sequence
+    fn stop_times_by_trip(&self) -> FastHashMap<&str, Vec<&StopTime>> {
+        let mut by_trip: FastHashMap<&str, Vec<&StopTime>> = FastHashMap::default();
+
+        for stop_time in &self.stop_times {
+            by_trip
+                .entry(stop_time.trip_id.as_str())
+                .or_default()
+                .push(stop_time);
+        }
+
+        for stop_times in by_trip.values_mut() {
+            stop_times.sort_by_key(|x| x.stop_sequence);
+        }
+
+        by_trip
+    }
+}
+
+/// Highest plausible speed between two stops for a mode of transport
+fn max_speed_kmh(route_type: &RouteType) -> f64 {
+    use RouteType::*;
+    match route_type {
+        Tram => 100.0,
+        Subway => 150.0,
+        Rail => 350.0,
+        Bus => 200.0,
+        Ferry => 80.0,
+        CableTram => 50.0,
+        AerialLift => 50.0,
+        Funicular => 50.0,
+        Trolleybus => 150.0,
+        Monorail => 150.0,
+    }
+}
+
+/// Stop location if it has plausible coordinates
+fn stop_point(stop: &Stop) -> Option<Point> {
+    let point = Point::new(stop.stop_lat?, stop.stop_lon?);
+    (point.is_valid() && !point.is_placeholder()).then_some(point)
+}
+
+/// Stops located far away from the rest of the feed
+pub fn outlying_stops(stops: &[Stop]) -> Vec<&Stop> {
+    let points: Vec<(&Stop, Point)> = stops
+        .iter()
+        .filter_map(|stop| Some((stop, stop_point(stop)?)))
+        .collect();
+
+    let Some(center) = median_point(points.iter().map(|(_, point)| point)) else {
+        return Vec::new();
+    };
+
+    points
+        .into_iter()
+        .filter(|(_, point)| haversine_m(&center, point) > MAX_DISTANCE_FROM_FEED_M)
+        .map(|(stop, _)| stop)
+        .collect()
+}
+
+/// Flag consecutive stops of a trip which are at the same place or imply implausible speed
+pub fn check_implied_speeds(input: &ValidationInput, report: &mut ValidationReport) {
+    let points: FastHashMap<&str, Point> = input
+        .stops
+        .iter()
+        .filter_map(|stop| Some((stop.stop_id.as_str(), stop_point(stop)?)))
+        .collect();
+
+    let route_types: FastHashMap<&str, &RouteType> = input
+        .routes
+        .iter()
+        .map(|route| (route.route_id.as_str(), &route.route_type))
+        .collect();
+
+    let by_trip = input.stop_times_by_trip();
+
+    for trip in &input.trips {
+        let Some(stop_times) = by_trip.get(trip.trip_id.as_str()) else {
+            continue;
+        };
+        let Some(route_type) = route_types.get(trip.route_id.as_str()) else {
+            continue;
+        };
+        let max_speed = max_speed_kmh(route_type);
+
+        for (from, to) in stop_times.iter().zip(stop_times.iter().skip(1)) {
+            // Flex rows are served at zones rather than stops
+            let (Some(from_stop), Some(to_stop)) = (&from.stop_id, &to.stop_id) else {
+                continue;
+            };
+            let (Some(from_point), Some(to_point)) =
+                (points.get(from_stop.as_str()), points.get(to_stop.as_str()))
+            else {
+                continue;
+            };
+
+            let distance = haversine_m(from_point, to_point);
+
+            if distance < ZERO_DISTANCE_M && from_stop != to_stop {
+                report.record(
+                    "zero_distance_stops",
+                    format!(
+                        "trip {}: stops {} and {} share coordinates",
+                        trip.trip_id, from_stop, to_stop
+                    ),
+                );
+                continue;
+            }
+
+            let (Some(departure), Some(arrival)) = (&from.departure_time, &to.arrival_time) else {
+                continue;
+            };
+            let (Ok(departure), Ok(arrival)) =
+                (parse_gtfs_time(departure), parse_gtfs_time(arrival))
+            else {
+                continue;
+            };
+
+            let seconds = arrival.saturating_sub(departure).max(MIN_HOP_SECONDS);
+            let speed = distance / seconds as f64 * 3.6;
+
+            if speed > max_speed {
+                report.record(
+                    "implied_speed",
+                    format!(
+                        "trip {}: {} -> {} at {:.0} km/h ({:.0} m in {} s)",
+                        trip.trip_id, from_stop, to_stop, speed, distance, seconds
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Flag stops lying far away from the shape of their trip
+pub fn check_stops_near_shapes(input: &ValidationInput, report: &mut ValidationReport) {
+    let lines = shape_lines(&input.shapes);
+
+    for snapped in snap_trips(&input.trips, &input.stops, &input.stop_times, &lines) {
+        for (stop_time, projection) in snapped.stops {
+            let Some(projection) = projection else {
+                continue;
+            };
+            if projection.offset_m > MAX_SHAPE_OFFSET_M {
+                report.record(
+                    "stop_far_from_shape",
+                    format!(
+                        "trip {}: stop {} is {:.0} m away from shape {}",
+                        snapped.trip.trip_id,
+                        stop_time.stop_id.as_deref().unwrap_or_default(),
+                        projection.offset_m,
+                        snapped.trip.shape_id.as_deref().unwrap_or_default()
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Check that records only reference ids defined in other tables
+pub fn check_references(input: &ValidationInput, report: &mut ValidationReport) {
+    let agency_ids: FastHashSet<&str> = input
+        .agencies
+        .iter()
+        .map(|x| x.agency_id.as_str())
+        .collect();
+    let route_ids: FastHashSet<&str> = input.routes.iter().map(|x| x.route_id.as_str()).collect();
+    let trip_ids: FastHashSet<&str> = input.trips.iter().map(|x| x.trip_id.as_str()).collect();
+    let stop_ids: FastHashSet<&str> = input.stops.iter().map(|x| x.stop_id.as_str()).collect();
+    let shape_ids: FastHashSet<&str> = input.shapes.iter().map(|x| x.shape_id.as_str()).collect();
+    let service_ids: FastHashSet<&str> = input
+        .calendars
+        .iter()
+        .map(|x| x.service_id.as_str())
+        .chain(input.calendar_dates.iter().map(|x| x.service_id.as_str()))
+        .collect();
+    let location_group_ids: FastHashSet<&str> = input
+        .location_groups
+        .iter()
+        .map(|x| x.location_group_id.as_str())
+        .collect();
+    let location_ids: FastHashSet<&str> = input.locations.iter().map(|x| x.id.as_str()).collect();
+    let booking_rule_ids: FastHashSet<&str> = input
+        .booking_rules
+        .iter()
+        .map(|x| x.booking_rule_id.as_str())
+        .collect();
+
+    // agency_id may be omitted when the feed has a single agency
+    let single_agency = input.agencies.len() == 1;
+
+    for route in &input.routes {
+        if route.agency_id.is_empty() && single_agency {
+            continue;
+        }
+        if !agency_ids.contains(route.agency_id.as_str()) {
+            report.record(
+                "route_unknown_agency",
+                format!(
+                    "route {}: agency {} not found",
+                    route.route_id, route.agency_id
+                ),
+            );
+        }
+    }
+
+    for trip in &input.trips {
+        if !route_ids.contains(trip.route_id.as_str()) {
+            report.record(
+                "trip_unknown_route",
+                format!("trip {}: route {} not found", trip.trip_id, trip.route_id),
+            );
+        }
+        if !service_ids.contains(trip.service_id.as_str()) {
+            report.record(
+                "trip_unknown_service",
+                format!(
+                    "trip {}: service {} not found in calendar or calendar_dates",
+                    trip.trip_id, trip.service_id
+                ),
+            );
+        }
+        if let Some(shape_id) = &trip.shape_id {
+            if !shape_ids.contains(shape_id.as_str()) {
+                report.record(
+                    "trip_unknown_shape",
+                    format!("trip {}: shape {} not found", trip.trip_id, shape_id),
+                );
+            }
+        }
+    }
+
+    for stop_time in &input.stop_times {
+        if !trip_ids.contains(stop_time.trip_id.as_str()) {
+            report.record(
+                "stop_time_unknown_trip",
+                format!(
+                    "stop time {} of trip {}: trip not found",
+                    stop_time.stop_sequence, stop_time.trip_id
+                ),
+            );
+        }
+        if stop_time.stop_id.is_none()
+            && stop_time.location_group_id.is_none()
+            && stop_time.location_id.is_none()
+        {
+            report.record(
+                "stop_time_without_location",
+                format!(
+                    "stop time {} of trip {}: none of stop_id, location_group_id or location_id given",
+                    stop_time.stop_sequence, stop_time.trip_id
+                ),
+            );
+        }
+        if let Some(stop_id) = &stop_time.stop_id {
+            if !stop_ids.contains(stop_id.as_str()) {
+                report.record(
+                    "stop_time_unknown_stop",
+                    format!(
+                        "stop time {} of trip {}: stop {} not found",
+                        stop_time.stop_sequence, stop_time.trip_id, stop_id
+                    ),
+                );
+            }
+        }
+        if let Some(location_group_id) = &stop_time.location_group_id {
+            if !location_group_ids.contains(location_group_id.as_str()) {
+                report.record(
+                    "stop_time_unknown_location_group",
+                    format!(
+                        "stop time {} of trip {}: location group {} not found",
+                        stop_time.stop_sequence, stop_time.trip_id, location_group_id
+                    ),
+                );
+            }
+        }
+        if let Some(location_id) = &stop_time.location_id {
+            if !location_ids.contains(location_id.as_str()) {
+                report.record(
+                    "stop_time_unknown_location",
+                    format!(
+                        "stop time {} of trip {}: location {} not found",
+                        stop_time.stop_sequence, stop_time.trip_id, location_id
+                    ),
+                );
+            }
+        }
+        let booking_rules = [
+            &stop_time.pickup_booking_rule_id,
+            &stop_time.drop_off_booking_rule_id,
+        ];
+        for booking_rule_id in booking_rules.into_iter().flatten() {
+            if !booking_rule_ids.contains(booking_rule_id.as_str()) {
+                report.record(
+                    "stop_time_unknown_booking_rule",
+                    format!(
+                        "stop time {} of trip {}: booking rule {} not found",
+                        stop_time.stop_sequence, stop_time.trip_id, booking_rule_id
+                    ),
+                );
+            }
+        }
+    }
+
+    for stop in &input.stops {
+        if let Some(parent_station) = &stop.parent_station {
+            if !stop_ids.contains(parent_station.as_str()) {
+                report.record(
+                    "stop_unknown_parent",
+                    format!(
+                        "stop {}: parent station {} not found",
+                        stop.stop_id, parent_station
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Report rows skipped while reading the feed, one finding per file
+pub fn check_row_errors(input: &ValidationInput, report: &mut ValidationReport) {
+    for errors in &input.row_errors {
+        report.record(
+            "malformed_row",
+            format!(
+                "{}: {} rows could not be parsed, {}",
+                errors.file_type.file_name(),
+                errors.count,
+                errors.samples.join("; ")
+            ),
+        );
+    }
+}
+
+/// Check that stop_sequence values of every trip are unique and listed in increasing order
+///
+/// Gaps are allowed by the specification but often point to stops dropped from the export.
+pub fn check_stop_sequences(input: &ValidationInput, report: &mut ValidationReport) {
+    let mut sequences: FastHashMap<&str, Vec<u64>> = FastHashMap::default();
+
+    for stop_time in &input.stop_times {
+        sequences
+            .entry(stop_time.trip_id.as_str())
+            .or_default()
+            .push(stop_time.stop_sequence);
+    }
+
+    for (trip_id, file_order) in sequences {
+        if file_order.windows(2).any(|x| x[0] > x[1]) {
+            report.record(
+                "stop_sequence_out_of_order",
+                format!("trip {trip_id}: stop times are not listed by stop_sequence"),
+            );
+        }
+
+        let mut sorted = file_order;
+        sorted.sort_unstable();
+
+        for pair in sorted.windows(2) {
+            if pair[0] == pair[1] {
+                report.record(
+                    "stop_sequence_duplicate",
+                    format!("trip {trip_id}: stop_sequence {} is used twice", pair[0]),
+                );
+            } else if pair[1] - pair[0] > 1 {
+                report.record(
+                    "stop_sequence_gap",
+                    format!(
+                        "trip {trip_id}: stop_sequence jumps from {} to {}",
+                        pair[0], pair[1]
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Renumber stop_sequence of every trip to 1, 2, 3...
+///
+/// Original ordering is kept, stop times sharing a stop_sequence keep their file order.
+pub fn renumber_stop_sequences(stop_times: &mut [StopTime]) {
+    let mut by_trip: FastHashMap<String, Vec<(u64, usize)>> = FastHashMap::default();
+
+    for (row, stop_time) in stop_times.iter().enumerate() {
+        by_trip
+            .entry(stop_time.trip_id.clone())
+            .or_default()
+            .push((stop_time.stop_sequence, row));
+    }
+
+    for rows in by_trip.values_mut() {
+        rows.sort();
+        for (new_sequence, (_, row)) in rows.iter().enumerate() {
+            stop_times[*row].stop_sequence = new_sequence as u64 + 1;
+        }
+    }
+}
+
+/// Stop time has an exact arrival or departure time
+fn is_exact_time(stop_time: &StopTime) -> bool {
+    let has_time = stop_time.arrival_time.is_some() || stop_time.departure_time.is_some();
+    let approximate = matches!(stop_time.timepoint, Some(TimePointType::Aproximate));
+    (has_time && !approximate) || has_window(stop_time)
+}
+
+/// Flex stop time served during a pickup and drop off window instead of at a time
+fn has_window(stop_time: &StopTime) -> bool {
+    stop_time.start_pickup_drop_off_window.is_some()
+        && stop_time.end_pickup_drop_off_window.is_some()
+}
+
+/// Check that trips carry times where interpolation needs them
+///
+/// Exact timepoints must have times, every trip must start and end with a time
+/// and every run of approximate stops must lie between two exact ones. Flex
+/// windows count as exact times.
+pub fn check_timepoints(input: &ValidationInput, report: &mut ValidationReport) {
+    let mut trips: FastHashMap<&str, Vec<&StopTime>> = FastHashMap::default();
+
+    for stop_time in &input.stop_times {
+        trips
+            .entry(stop_time.trip_id.as_str())
+            .or_default()
+            .push(stop_time);
+
+        let exact = matches!(stop_time.timepoint, Some(TimePointType::Exact));
+        if exact && (stop_time.arrival_time.is_none() || stop_time.departure_time.is_none()) {
+            report.record(
+                "timepoint_without_time",
+                format!(
+                    "trip {}: exact timepoint at stop_sequence {} has no time",
+                    stop_time.trip_id, stop_time.stop_sequence
+                ),
+            );
+        }
+    }
+
+    for (trip_id, mut stop_times) in trips {
+        stop_times.sort_by_key(|x| x.stop_sequence);
+
+        let endpoints = [stop_times.first(), stop_times.last()];
+        for stop_time in endpoints.into_iter().flatten() {
+            if stop_time.arrival_time.is_none()
+                && stop_time.departure_time.is_none()
+                && !has_window(stop_time)
+            {
+                report.record(
+                    "trip_endpoint_without_time",
+                    format!(
+                        "trip {trip_id}: first or last stop_sequence {} has no time",
+                        stop_time.stop_sequence
+                    ),
+                );
+            }
+        }
+
+        // Runs of approximate stops touching the start or the end of the trip
+        // have nothing to interpolate from
+        let first_exact = stop_times.iter().position(|x| is_exact_time(x));
+        let last_exact = stop_times.iter().rposition(|x| is_exact_time(x));
+        let unbounded = match (first_exact, last_exact) {
+            (Some(first), Some(last)) => first > 0 || last < stop_times.len() - 1,
+            _ => true,
+        };
+        if unbounded {
+            report.record(
+                "approximate_span_unbounded",
+                format!("trip {trip_id}: approximate stops are not enclosed by exact timepoints"),
+            );
+        }
+    }
+}
+
+/// Flag calendars which can not produce any service
+pub fn check_calendars(input: &ValidationInput, report: &mut ValidationReport) {
+    let added_services: FastHashSet<&str> = input
+        .calendar_dates
+        .iter()
+        .filter(|x| matches!(x.exception_type, SerivceExceptionType::Added))
+        .map(|x| x.service_id.as_str())
+        .collect();
+
+    let feed_start = input
+        .feed_info
+        .first()
+        .and_then(|x| x.feed_start_date.as_deref())
+        .and_then(|x| parse_gtfs_date(x).ok());
+
+    for calendar in &input.calendars {
+        let (start, end) = match (
+            parse_gtfs_date(&calendar.start_date),
+            parse_gtfs_date(&calendar.end_date),
+        ) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(err), _) | (_, Err(err)) => {
+                report.record(
+                    "calendar_invalid_date",
+                    format!("service {}: {}", calendar.service_id, err),
+                );
+                continue;
+            }
+        };
+
+        if end < start {
+            report.record(
+                "calendar_reversed_range",
+                format!(
+                    "service {}: end_date {} is before start_date {}",
+                    calendar.service_id, calendar.end_date, calendar.start_date
+                ),
+            );
+        }
+
+        let has_added_dates = added_services.contains(calendar.service_id.as_str());
+
+        if !calendar.runs_on_any_weekday() && !has_added_dates {
+            report.record(
+                "calendar_without_service",
+                format!(
+                    "service {}: no weekday enabled and no dates added",
+                    calendar.service_id
+                ),
+            );
+        }
+
+        if let Some(feed_start) = feed_start {
+            if end < feed_start && !has_added_dates {
+                report.record(
+                    "calendar_expired",
+                    format!(
+                        "service {}: ends on {} before the feed starts",
+                        calendar.service_id, calendar.end_date
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// All dates on which any service of the feed operates
+///
+/// Services with malformed calendars are skipped, they are reported by `check_calendars`.
+fn service_dates(input: &ValidationInput) -> BTreeSet<NaiveDate> {
+    let mut calendar_dates: FastHashMap<&str, Vec<&CalendarDate>> = FastHashMap::default();
+    for calendar_date in &input.calendar_dates {
+        calendar_dates
+            .entry(calendar_date.service_id.as_str())
+            .or_default()
+            .push(calendar_date);
+    }
+
+    let mut dates = BTreeSet::new();
+
+    for calendar in &input.calendars {
+        let exceptions = calendar_dates
+            .remove(calendar.service_id.as_str())
+            .unwrap_or_default();
+        if let Ok(service) = expand_dates(Some(calendar), exceptions) {
+            dates.extend(service);
+        }
+    }
+
+    // Services defined by calendar_dates only
+    for exceptions in calendar_dates.into_values() {
+        if let Ok(service) = expand_dates(None, exceptions) {
+            dates.extend(service);
+        }
+    }
+
+    dates
+}
+
+/// Compare the feed_info validity window with the dates services actually run on
+pub fn check_feed_window(input: &ValidationInput, report: &mut ValidationReport) {
+    let Some(feed_info) = input.feed_info.first() else {
+        return;
+    };
+
+    let mut parse = |value: &Option<String>| match value.as_deref().map(parse_gtfs_date) {
+        Some(Ok(date)) => Some(date),
+        Some(Err(err)) => {
+            report.record("feed_info_invalid_date", err.to_string());
+            None
+        }
+        None => None,
+    };
+    let feed_start = parse(&feed_info.feed_start_date);
+    let feed_end = parse(&feed_info.feed_end_date);
+
+    if feed_start.is_none() && feed_end.is_none() {
+        return;
+    }
+
+    let dates = service_dates(input);
+    let (Some(first), Some(last)) = (dates.first(), dates.last()) else {
+        return;
+    };
+
+    let window = format!(
+        "{}..{}",
+        feed_info.feed_start_date.as_deref().unwrap_or(""),
+        feed_info.feed_end_date.as_deref().unwrap_or("")
+    );
+
+    if feed_start.is_some_and(|x| *first < x) || feed_end.is_some_and(|x| *last > x) {
+        report.record(
+            "service_outside_feed_window",
+            format!("service runs {first}..{last}, feed window is {window}"),
+        );
+    }
+
+    let in_window = dates.iter().any(|x| {
+        feed_start.is_none_or(|start| *x >= start) && feed_end.is_none_or(|end| *x <= end)
+    });
+    if !in_window {
+        report.record(
+            "feed_window_without_service",
+            format!("no service runs within feed window {window}"),
+        );
+    }
+}
+
+fn check_point(report: &mut ValidationReport, point: &Point, description: String) {
+    if !point.is_valid() {
+        report.record(
+            "coordinate_out_of_range",
+            format!("{description}: ({}, {})", point.lat, point.lon),
+        );
+    } else if point.is_placeholder() {
+        report.record(
+            "coordinate_placeholder",
+            format!("{description}: located at (0, 0)"),
+        );
+    }
+}
+
+/// Flag invalid or placeholder coordinates and stops far away from the rest of the feed
+pub fn check_coordinates(input: &ValidationInput, report: &mut ValidationReport) {
+    for stop in &input.stops {
+        if let (Some(lat), Some(lon)) = (stop.stop_lat, stop.stop_lon) {
+            check_point(
+                report,
+                &Point::new(lat, lon),
+                format!("stop {}", stop.stop_id),
+            );
+        }
+    }
+
+    for shape in &input.shapes {
+        check_point(
+            report,
+            &Point::new(shape.shape_pt_lat, shape.shape_pt_lon),
+            format!("shape {} point {}", shape.shape_id, shape.shape_pt_sequence),
+        );
+    }
+
+    for stop in outlying_stops(&input.stops) {
+        report.record(
+            "stop_far_from_feed",
+            format!(
+                "stop {}: ({}, {}) is far away from other stops",
+                stop.stop_id,
+                stop.stop_lat.unwrap_or_default(),
+                stop.stop_lon.unwrap_or_default()
+            ),
+        );
+    }
+}
+
+/// Flag malformed route colors and text hard to read on the route color
+pub fn check_route_colors(input: &ValidationInput, report: &mut ValidationReport) {
+    for route in &input.routes {
+        for color in [&route.route_color, &route.route_text_color]
+            .into_iter()
+            .flatten()
+        {
+            if let Color::Invalid(value) = color {
+                report.record(
+                    "color_invalid",
+                    format!(
+                        "route {}: {} is not a six digit hex color",
+                        route.route_id, value
+                    ),
+                );
+            }
+        }
+
+        let (Some(color), Some(text_color)) = (&route.route_color, &route.route_text_color) else {
+            continue;
+        };
+        let Some(contrast) = color.contrast_ratio(text_color) else {
+            continue;
+        };
+        if contrast < MIN_COLOR_CONTRAST {
+            report.record(
+                "route_color_contrast",
+                format!(
+                    "route {}: text {} on {} has contrast {:.1}",
+                    route.route_id,
+                    text_color.as_str(),
+                    color.as_str(),
+                    contrast
+                ),
+            );
+        }
+    }
+}
+
+/// Run all checks with default severities
+pub fn validate(input: &ValidationInput) -> ValidationReport {
+    validate_with_rules(input, &RuleSet::default())
+}
+
+/// Run all checks, dropping disabled rules and applying severity overrides
+pub fn validate_with_rules(input: &ValidationInput, rules: &RuleSet) -> ValidationReport {
+    let span = tracing::info_span!("validate", findings = tracing::field::Empty);
+    let _entered = span.enter();
+    let mut report = ValidationReport::default();
+
+    check_row_errors(input, &mut report);
+    check_references(input, &mut report);
+    check_stop_sequences(input, &mut report);
+    check_timepoints(input, &mut report);
+    check_calendars(input, &mut report);
+    check_feed_window(input, &mut report);
+    check_coordinates(input, &mut report);
+    check_route_colors(input, &mut report);
+    check_implied_speeds(input, &mut report);
+    check_stops_near_shapes(input, &mut report);
+
+    report.apply_rules(rules);
+    span.record("findings", report.findings.len());
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+    use std::io::{BufRead, Cursor, Read};
+
+    use crate::gtfs::{
+        BookingRule, BookingType, Calendar, Color, FeedInfo, GtfsFileType, GtfsStore,
+        LocationGroup, TimePointType,
+    };
+
+    use super::{renumber_stop_sequences, validate, ValidationInput};
+
+    fn synthetic_input() -> ValidationInput {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 2,
+            max_edits: 0,
+            ..Default::default()
+        });
+
+        ValidationInput::from_store(&mut feed).unwrap()
+    }
+
+    #[test]
+    fn test_clean_feed() {
+        let report = validate(&synthetic_input());
+        assert!(report.is_empty(), "{:?}", report);
+    }
+
+    #[test]
+    fn test_implied_speed() {
+        let mut input = synthetic_input();
+        // Move one stop to the other side of the globe
+        input.stops[3].stop_lat = Some(-52.0);
+
+        let report = validate(&input);
+        let finding = report
+            .findings
+            .iter()
+            .find(|x| x.check == "implied_speed")
+            .unwrap();
+        assert!(finding.count > 0);
+    }
+
+    #[test]
+    fn test_zero_distance() {
+        let mut input = synthetic_input();
+        input.stops[4].stop_lat = input.stops[3].stop_lat;
+        input.stops[4].stop_lon = input.stops[3].stop_lon;
+
+        let report = validate(&input);
+        assert!(report
+            .findings
+            .iter()
+            .any(|x| x.check == "zero_distance_stops"));
+    }
+
+    #[test]
+    fn test_references() {
+        let mut input = synthetic_input();
+        input.stops.remove(0);
+        input.trips[0].service_id = "unknown".to_string();
+
+        let report = validate(&input);
+        let count = |check: &str| {
+            report
+                .findings
+                .iter()
+                .find(|x| x.check == check)
+                .map(|x| x.count)
+        };
+
+        assert!(count("stop_time_unknown_stop").unwrap() > 0);
+        assert_eq!(count("trip_unknown_service"), Some(1));
+        assert_eq!(count("trip_unknown_route"), None);
+    }
+
+    #[test]
+    fn test_flex_references() {
+        let mut input = synthetic_input();
+        input.location_groups.push(LocationGroup {
+            location_group_id: "g1".to_string(),
+            location_group_name: None,
+        });
+        input.booking_rules.push(BookingRule {
+            booking_rule_id: "b1".to_string(),
+            booking_type: BookingType::RealTime,
+            prior_notice_duration_min: None,
+            prior_notice_duration_max: None,
+            prior_notice_last_day: None,
+            prior_notice_last_time: None,
+            prior_notice_start_day: None,
+            prior_notice_start_time: None,
+            prior_notice_service_id: None,
+            message: None,
+            pickup_message: None,
+            drop_off_message: None,
+            phone_number: None,
+            info_url: None,
+            booking_url: None,
+        });
+
+        let served_at_group = &mut input.stop_times[0];
+        served_at_group.stop_id = None;
+        served_at_group.location_group_id = Some("g1".to_string());
+        served_at_group.pickup_booking_rule_id = Some("b1".to_string());
+        input.stop_times[1].stop_id = None;
+        input.stop_times[1].location_id = Some("z9".to_string());
+        input.stop_times[2].stop_id = None;
+        input.stop_times[3].drop_off_booking_rule_id = Some("b9".to_string());
+
+        let report = validate(&input);
+        let count = |check: &str| {
+            report
+                .findings
+                .iter()
+                .find(|x| x.check == check)
+                .map(|x| x.count)
+        };
+
+        assert_eq!(count("stop_time_unknown_location_group"), None);
+        assert_eq!(count("stop_time_unknown_location"), Some(1));
+        assert_eq!(count("stop_time_without_location"), Some(1));
+        assert_eq!(count("stop_time_unknown_booking_rule"), Some(1));
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let mut input = synthetic_input();
+        input.stop_times[1].stop_sequence = 1;
+        input.stop_times[5].stop_sequence = 10;
+
+        let report = validate(&input);
+        for check in [
+            "stop_sequence_duplicate",
+            "stop_sequence_out_of_order",
+            "stop_sequence_gap",
+        ] {
+            assert!(report.findings.iter().any(|x| x.check == check), "{check}");
+        }
+
+        renumber_stop_sequences(&mut input.stop_times);
+        let report = validate(&input);
+        assert!(report
+            .findings
+            .iter()
+            .all(|x| x.check == "stop_sequence_out_of_order"));
+    }
+
+    #[test]
+    fn test_calendars() {
+        let mut input = synthetic_input();
+        let start = NaiveDate::from_ymd_opt(2023, 6, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2023, 5, 1).unwrap();
+        input
+            .calendars
+            .push(Calendar::weekly("reversed", &start, &end, [true; 7]));
+        input
+            .calendars
+            .push(Calendar::weekly("idle", &end, &start, [false; 7]));
+
+        let report = validate(&input);
+        for check in ["calendar_reversed_range", "calendar_without_service"] {
+            let finding = report.findings.iter().find(|x| x.check == check).unwrap();
+            assert_eq!(finding.count, 1);
+        }
+    }
+
+    #[test]
+    fn test_coordinates() {
+        let mut input = synthetic_input();
+        input.stops[0].stop_lat = Some(95.0);
+        input.stops[1].stop_lat = Some(0.0);
+        input.stops[1].stop_lon = Some(0.0);
+        input.stops[2].stop_lat = Some(-33.9);
+        input.stops[2].stop_lon = Some(18.4);
+
+        let report = validate(&input);
+        for check in [
+            "coordinate_out_of_range",
+            "coordinate_placeholder",
+            "stop_far_from_feed",
+        ] {
+            let finding = report.findings.iter().find(|x| x.check == check).unwrap();
+            assert_eq!(finding.count, 1, "{check}");
+        }
+    }
+
+    #[test]
+    fn test_route_colors() {
+        let mut input = synthetic_input();
+        input.routes[0].route_color = Some(Color::parse("FFFFFF"));
+        input.routes[0].route_text_color = Some(Color::parse("BBBBBB"));
+        input.routes[1].route_color = Some(Color::parse("#12345"));
+
+        let report = validate(&input);
+        for check in ["color_invalid", "route_color_contrast"] {
+            let finding = report.findings.iter().find(|x| x.check == check).unwrap();
+            assert_eq!(finding.count, 1, "{check}");
+        }
+    }
+
+    #[test]
+    fn test_timepoints() {
+        let mut input = synthetic_input();
+        let trip_id = input.stop_times[0].trip_id.clone();
+
+        let first = &mut input.stop_times[0];
+        first.timepoint = Some(TimePointType::Exact);
+        first.arrival_time = None;
+        first.departure_time = None;
+
+        let report = validate(&input);
+        let checks: Vec<_> = report.findings.iter().map(|x| x.check).collect();
+        assert_eq!(
+            checks,
+            [
+                "timepoint_without_time",
+                "trip_endpoint_without_time",
+                "approximate_span_unbounded"
+            ]
+        );
+        assert!(report.findings[0].samples[0].contains(&trip_id));
+    }
+
+    #[test]
+    fn test_flex_trip_timepoints() {
+        let mut input = synthetic_input();
+        let trip_id = input.stop_times[0].trip_id.clone();
+
+        // Zone to zone service booked for a window, without arrival or departure times
+        for stop_time in input.stop_times.iter_mut().filter(|x| x.trip_id == trip_id) {
+            stop_time.arrival_time = None;
+            stop_time.departure_time = None;
+            stop_time.start_pickup_drop_off_window = Some("08:00:00".to_string());
+            stop_time.end_pickup_drop_off_window = Some("12:00:00".to_string());
+        }
+
+        let report = validate(&input);
+        assert!(report.is_empty(), "{:?}", report);
+    }
+
+    fn feed_info(start_date: &str, end_date: &str) -> FeedInfo {
+        FeedInfo {
+            feed_publisher_name: "Test".to_string(),
+            feed_publisher_url: "https://example.com".to_string(),
+            feed_lang: "en".to_string(),
+            default_lang: None,
+            feed_start_date: Some(start_date.to_string()),
+            feed_end_date: Some(end_date.to_string()),
+            feed_version: None,
+            feed_contact_email: None,
+            feed_contact_url: None,
+        }
+    }
+
+    #[test]
+    fn test_feed_window() {
+        // Synthetic feed runs through 2023
+        let mut input = synthetic_input();
+        input.feed_info = vec![feed_info("20230101", "20231231")];
+        assert!(validate(&input).is_empty());
+
+        input.feed_info = vec![feed_info("20230101", "20230630")];
+        let report = validate(&input);
+        let checks: Vec<_> = report.findings.iter().map(|x| x.check).collect();
+        assert_eq!(checks, ["service_outside_feed_window"]);
+
+        input.feed_info = vec![feed_info("20240101", "20241231")];
+        let report = validate(&input);
+        let checks: Vec<_> = report.findings.iter().map(|x| x.check).collect();
+        assert_eq!(
+            checks,
+            [
+                "calendar_expired",
+                "service_outside_feed_window",
+                "feed_window_without_service"
+            ]
+        );
+    }
+
+    /// Synthetic feed with an unparsable stop_times row appended
+    struct WithBadRow(SyntheticFeed);
+
+    impl GtfsStore for WithBadRow {
+        fn get_readable<'a>(
+            &'a mut self,
+            file_type: GtfsFileType,
+        ) -> Option<Box<dyn BufRead + 'a>> {
+            let mut text = String::new();
+            self.0
+                .get_readable(file_type)?
+                .read_to_string(&mut text)
+                .unwrap();
+            if file_type == GtfsFileType::StopTimes {
+                text.push_str("trip,08:00:00\n");
+            }
+            Some(Box::new(Cursor::new(text)))
+        }
+    }
+
+    #[test]
+    fn test_malformed_rows_are_skipped() {
+        let mut store = WithBadRow(SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 2,
+            max_edits: 0,
+            ..Default::default()
+        }));
+        let stop_times = store.0.stop_times.len();
+
+        let input = ValidationInput::from_store(&mut store).unwrap();
+        assert_eq!(input.stop_times.len(), stop_times);
+
+        let report = validate(&input);
+        let checks: Vec<_> = report.findings.iter().map(|x| x.check).collect();
+        assert_eq!(checks, ["malformed_row"]);
+        assert!(report.findings[0].samples[0].starts_with("stop_times: 1 rows"));
+    }
+}
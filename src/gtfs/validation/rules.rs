@@ -0,0 +1,156 @@
+/// Named validation rules, their severities and per-consumer overrides
+///
+use std::{fmt, fs::File, io::BufReader, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::FastHashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Process exit code when this is the highest severity found, info does not fail the run
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Severity::Info => 0,
+            Severity::Warning => 1,
+            Severity::Error => 2,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        f.pad(value)
+    }
+}
+
+/// Every check recorded by the validator with its default severity
+pub const RULES: &[(&str, Severity)] = &[
+    ("malformed_row", Severity::Error),
+    ("route_unknown_agency", Severity::Error),
+    ("trip_unknown_route", Severity::Error),
+    ("trip_unknown_service", Severity::Error),
+    ("trip_unknown_shape", Severity::Error),
+    ("stop_time_unknown_trip", Severity::Error),
+    ("stop_time_unknown_stop", Severity::Error),
+    ("stop_time_unknown_location_group", Severity::Error),
+    ("stop_time_unknown_location", Severity::Error),
+    ("stop_time_unknown_booking_rule", Severity::Error),
+    ("stop_time_without_location", Severity::Error),
+    ("stop_unknown_parent", Severity::Error),
+    ("stop_sequence_out_of_order", Severity::Info),
+    ("stop_sequence_duplicate", Severity::Error),
+    ("stop_sequence_gap", Severity::Info),
+    ("timepoint_without_time", Severity::Error),
+    ("trip_endpoint_without_time", Severity::Error),
+    ("approximate_span_unbounded", Severity::Warning),
+    ("calendar_invalid_date", Severity::Error),
+    ("calendar_reversed_range", Severity::Error),
+    ("calendar_without_service", Severity::Warning),
+    ("calendar_expired", Severity::Warning),
+    ("feed_info_invalid_date", Severity::Error),
+    ("service_outside_feed_window", Severity::Warning),
+    ("feed_window_without_service", Severity::Error),
+    ("coordinate_out_of_range", Severity::Error),
+    ("coordinate_placeholder", Severity::Warning),
+    ("stop_far_from_feed", Severity::Warning),
+    ("color_invalid", Severity::Error),
+    ("route_color_contrast", Severity::Info),
+    ("implied_speed", Severity::Warning),
+    ("zero_distance_stops", Severity::Info),
+    ("stop_far_from_shape", Severity::Warning),
+];
+
+/// Default severity of a rule, unknown rules are treated as errors
+pub fn default_severity(rule: &str) -> Severity {
+    RULES
+        .iter()
+        .find(|(name, _)| *name == rule)
+        .map(|(_, severity)| *severity)
+        .unwrap_or(Severity::Error)
+}
+
+/// Rule overrides read from a json config file
+///
+/// ```json
+/// {"disabled": ["stop_sequence_gap"], "severity": {"route_color_contrast": "error"}}
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    #[serde(default)]
+    disabled: Vec<String>,
+    #[serde(default)]
+    severity: FastHashMap<String, Severity>,
+}
+
+impl RuleSet {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Could not open {path:?}"))?;
+        let rules: RuleSet = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Could not parse rules from {path:?}"))?;
+        rules.check_names()?;
+        Ok(rules)
+    }
+
+    /// Fail on rule names the validator does not know, those are most likely typos
+    fn check_names(&self) -> Result<()> {
+        for rule in self.disabled.iter().chain(self.severity.keys()) {
+            if !RULES.iter().any(|(name, _)| name == rule) {
+                bail!("Unknown validation rule {rule}")
+            }
+        }
+        Ok(())
+    }
+
+    /// Severity of a rule after overrides, None if the rule is disabled
+    pub fn severity(&self, rule: &str) -> Option<Severity> {
+        if self.disabled.iter().any(|x| x == rule) {
+            return None;
+        }
+        Some(
+            self.severity
+                .get(rule)
+                .copied()
+                .unwrap_or_else(|| default_severity(rule)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let rules: RuleSet = serde_json::from_str(
+            r#"{"disabled": ["stop_sequence_gap"], "severity": {"route_color_contrast": "error"}}"#,
+        )
+        .unwrap();
+        rules.check_names().unwrap();
+
+        assert_eq!(rules.severity("stop_sequence_gap"), None);
+        assert_eq!(
+            rules.severity("route_color_contrast"),
+            Some(Severity::Error)
+        );
+        assert_eq!(rules.severity("calendar_expired"), Some(Severity::Warning));
+
+        let typo: RuleSet = serde_json::from_str(r#"{"disabled": ["stop_sequense_gap"]}"#).unwrap();
+        assert!(typo.check_names().is_err());
+    }
+}
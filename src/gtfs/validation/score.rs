@@ -0,0 +1,123 @@
+/// Single quality score of a feed combining validation results and data coverage
+///
+use super::{rules::Severity, ValidationInput, ValidationReport};
+use crate::gtfs::Trip;
+
+/// Share of the score coming from validation findings, the rest is data coverage
+const VALIDATION_WEIGHT: f64 = 0.6;
+
+/// Penalty of a single violation relative to the feed size
+fn severity_weight(severity: Severity) -> f64 {
+    match severity {
+        Severity::Error => 10.0,
+        Severity::Warning => 3.0,
+        Severity::Info => 1.0,
+    }
+}
+
+/// Part of the score, value is between 0 and 1
+#[derive(Debug)]
+pub struct ScoreComponent {
+    pub name: &'static str,
+    pub weight: f64,
+    pub value: f64,
+}
+
+#[derive(Debug)]
+pub struct QualityScore {
+    pub components: Vec<ScoreComponent>,
+}
+
+impl QualityScore {
+    /// Weighted score between 0 and 100
+    pub fn total(&self) -> f64 {
+        let weights: f64 = self.components.iter().map(|x| x.weight).sum();
+        let score: f64 = self.components.iter().map(|x| x.weight * x.value).sum();
+        100.0 * score / weights
+    }
+
+    pub fn print(&self) {
+        println!("Quality score: {:.1}", self.total());
+        for component in &self.components {
+            println!(
+                "    {:<20} {:>5.1} (weight {:.2})",
+                component.name,
+                100.0 * component.value,
+                component.weight
+            );
+        }
+    }
+}
+
+/// Share of trips having a property, 1 for a feed without trips
+fn trip_share<P: Fn(&Trip) -> bool>(trips: &[Trip], predicate: P) -> f64 {
+    if trips.is_empty() {
+        return 1.0;
+    }
+    trips.iter().filter(|x| predicate(x)).count() as f64 / trips.len() as f64
+}
+
+pub fn quality_score(input: &ValidationInput, report: &ValidationReport) -> QualityScore {
+    let records =
+        input.routes.len() + input.trips.len() + input.stops.len() + input.stop_times.len();
+
+    let penalty: f64 = report
+        .findings
+        .iter()
+        .map(|x| severity_weight(x.severity) * x.count as f64 / records.max(1) as f64)
+        .sum();
+
+    let coverage_weight = (1.0 - VALIDATION_WEIGHT) / 3.0;
+
+    QualityScore {
+        components: vec![
+            ScoreComponent {
+                name: "validation",
+                weight: VALIDATION_WEIGHT,
+                value: (1.0 - penalty).max(0.0),
+            },
+            ScoreComponent {
+                name: "shapes",
+                weight: coverage_weight,
+                value: trip_share(&input.trips, |x| x.shape_id.is_some()),
+            },
+            ScoreComponent {
+                name: "headsigns",
+                weight: coverage_weight,
+                value: trip_share(&input.trips, |x| x.trip_headsign.is_some()),
+            },
+            ScoreComponent {
+                name: "accessibility",
+                weight: coverage_weight,
+                value: trip_share(&input.trips, |x| x.wheelchair_accessible.is_some()),
+            },
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+    use crate::gtfs::validation::validate;
+
+    #[test]
+    fn test_quality_score() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 2,
+            max_edits: 0,
+            ..Default::default()
+        });
+        let input = ValidationInput::from_store(&mut feed).unwrap();
+        let clean = quality_score(&input, &validate(&input));
+
+        // Synthetic trips have headsigns but neither shapes nor accessibility
+        let expected = 100.0 * (VALIDATION_WEIGHT + (1.0 - VALIDATION_WEIGHT) / 3.0);
+        assert!((clean.total() - expected).abs() < 1e-9);
+
+        let mut broken = input;
+        broken.trips[0].route_id = "missing".to_string();
+        let score = quality_score(&broken, &validate(&broken));
+        assert!(score.total() < clean.total());
+    }
+}
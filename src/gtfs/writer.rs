@@ -0,0 +1,268 @@
+/// Writing tables back into a gtfs zip, for pipelines transforming feeds
+///
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, Write},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{
+    validation::ValidationInput, Agency, Attribution, BookingRule, Calendar, CalendarDate,
+    FareAttribute, FareRule, FeedInfo, Frequency, GtfsError, GtfsFile, GtfsFileType, GtfsStore,
+    Level, Location, LocationGroup, LocationGroupStop, PathWay, Route, Shape, Stop, StopTime,
+    StoreError, TicketingDeepLink, TicketingIdentifier, Transfer, Translation, Trip,
+};
+use crate::csv::CsvTableWriter;
+
+/// Writes each table as a csv member of a zip, empty tables are left out
+pub struct GtfsWriter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    written: Vec<GtfsFileType>,
+}
+
+impl GtfsWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|source| StoreError::Write {
+            path: path.to_string_lossy().to_string(),
+            source,
+        })?;
+        Ok(GtfsWriter::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write + Seek> GtfsWriter<W> {
+    pub fn new(writer: W) -> Self {
+        GtfsWriter {
+            zip: ZipWriter::new(writer),
+            written: Vec::new(),
+        }
+    }
+
+    /// Add a table as <file_name>.txt, each table may be written once
+    pub fn write_table<'a, I, T>(&mut self, items: T) -> Result<(), GtfsError>
+    where
+        I: Serialize + GtfsFile + 'a,
+        T: IntoIterator<Item = &'a I>,
+    {
+        let file_type = I::get_file_type();
+        let name = format!("{}.txt", file_type.file_name());
+        if self.written.contains(&file_type) {
+            return Err(StoreError::DuplicateFile(name).into());
+        }
+
+        let mut items = items.into_iter().peekable();
+        if items.peek().is_none() {
+            return Ok(());
+        }
+
+        self.start_file(&name)?;
+
+        let write_error = |source: io::Error| StoreError::Write {
+            path: name.clone(),
+            source,
+        };
+        let mut writer = CsvTableWriter::from_writer(&mut self.zip);
+        for item in items {
+            writer.write_row(item).map_err(write_error)?;
+        }
+        writer.into_inner().map_err(write_error)?;
+
+        self.written.push(file_type);
+        Ok(())
+    }
+
+    fn start_file(&mut self, name: &str) -> Result<(), StoreError> {
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        Ok(self.zip.start_file(name, options)?)
+    }
+
+    /// Add zones of flexible services as locations.geojson
+    pub fn write_locations(&mut self, locations: &[Location]) -> Result<(), GtfsError> {
+        let name = format!("{}.geojson", GtfsFileType::Locations.file_name());
+        if self.written.contains(&GtfsFileType::Locations) {
+            return Err(StoreError::DuplicateFile(name).into());
+        }
+        if locations.is_empty() {
+            return Ok(());
+        }
+
+        let features: Vec<_> = locations
+            .iter()
+            .map(|x| {
+                json!({
+                    "type": "Feature",
+                    "id": x.id,
+                    "properties": x.properties,
+                    "geometry": x.geometry,
+                })
+            })
+            .collect();
+        let collection = json!({"type": "FeatureCollection", "features": features});
+
+        self.start_file(&name)?;
+        serde_json::to_writer(&mut self.zip, &collection).map_err(|x| StoreError::Write {
+            path: name.clone(),
+            source: x.into(),
+        })?;
+
+        self.written.push(GtfsFileType::Locations);
+        Ok(())
+    }
+
+    /// Copy a table of a store, nothing if the file is not present
+    fn copy_table<I, S>(&mut self, store: &mut S) -> Result<(), GtfsError>
+    where
+        I: Serialize + DeserializeOwned + GtfsFile,
+        S: GtfsStore,
+    {
+        let items: Vec<I> = store.try_read_all()?;
+        self.write_table(&items)
+    }
+
+    /// Add every table of a store, one at a time
+    pub fn write_store<S: GtfsStore>(&mut self, store: &mut S) -> Result<(), GtfsError> {
+        self.copy_table::<Agency, S>(store)?;
+        self.copy_table::<Stop, S>(store)?;
+        self.copy_table::<Route, S>(store)?;
+        self.copy_table::<Trip, S>(store)?;
+        self.copy_table::<StopTime, S>(store)?;
+        self.copy_table::<Calendar, S>(store)?;
+        self.copy_table::<CalendarDate, S>(store)?;
+        self.copy_table::<FareAttribute, S>(store)?;
+        self.copy_table::<FareRule, S>(store)?;
+        self.copy_table::<Shape, S>(store)?;
+        self.copy_table::<Frequency, S>(store)?;
+        self.copy_table::<Transfer, S>(store)?;
+        self.copy_table::<PathWay, S>(store)?;
+        self.copy_table::<Level, S>(store)?;
+        self.copy_table::<FeedInfo, S>(store)?;
+        self.copy_table::<Translation, S>(store)?;
+        self.copy_table::<Attribution, S>(store)?;
+        self.copy_table::<TicketingIdentifier, S>(store)?;
+        self.copy_table::<TicketingDeepLink, S>(store)?;
+        self.copy_table::<LocationGroup, S>(store)?;
+        self.copy_table::<LocationGroupStop, S>(store)?;
+        self.copy_table::<BookingRule, S>(store)?;
+        self.write_locations(&store.read_locations()?)?;
+        Ok(())
+    }
+
+    /// Add all tables read for validation
+    pub fn write_input(&mut self, input: &ValidationInput) -> Result<(), GtfsError> {
+        self.write_table(&input.agencies)?;
+        self.write_table(&input.routes)?;
+        self.write_table(&input.trips)?;
+        self.write_table(&input.stops)?;
+        self.write_table(&input.stop_times)?;
+        self.write_table(&input.shapes)?;
+        self.write_table(&input.calendars)?;
+        self.write_table(&input.calendar_dates)?;
+        self.write_table(&input.feed_info)?;
+        self.write_table(&input.location_groups)?;
+        self.write_table(&input.location_group_stops)?;
+        self.write_table(&input.booking_rules)?;
+        self.write_locations(&input.locations)?;
+        Ok(())
+    }
+
+    /// Write the zip directory, the feed is incomplete until then
+    pub fn finish(mut self) -> Result<W, GtfsError> {
+        Ok(self.zip.finish().map_err(StoreError::from)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+    use crate::gtfs::{GtfsStore, GtfsZipStore, Stop, StopTime};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 2,
+            ..Default::default()
+        });
+        let input = ValidationInput::from_store(&mut feed).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("rdtfs-writer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("feed.zip");
+
+        let mut writer = GtfsWriter::create(&path).unwrap();
+        writer.write_input(&input).unwrap();
+        assert!(matches!(
+            writer.write_table(&input.stops),
+            Err(GtfsError::Store(StoreError::DuplicateFile(_)))
+        ));
+        writer.finish().unwrap();
+
+        let mut store = GtfsZipStore::from_file(path.to_str().unwrap()).unwrap();
+        let stops: Vec<Stop> = store.read_all().unwrap();
+        let stop_times: Vec<StopTime> = store.read_all().unwrap();
+        assert_eq!(stops.len(), input.stops.len());
+        assert_eq!(stop_times.len(), input.stop_times.len());
+        assert_eq!(stop_times[3].arrival_time, input.stop_times[3].arrival_time);
+        // Empty tables are left out
+        assert!(store
+            .try_read_all::<crate::gtfs::Shape>()
+            .unwrap()
+            .is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_store() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 2,
+            ..Default::default()
+        });
+        let dir = std::env::temp_dir().join(format!("rdtfs-writer-store-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (source, copy) = (dir.join("source.zip"), dir.join("copy.zip"));
+
+        let mut writer = GtfsWriter::create(&source).unwrap();
+        writer.write_store(&mut feed).unwrap();
+        let level = Level {
+            level_id: "l1".to_string(),
+            level_index: -1.0,
+            level_name: Some("Platforms".to_string()),
+        };
+        writer.write_table(&[level]).unwrap();
+        let group = LocationGroup {
+            location_group_id: "g1".to_string(),
+            location_group_name: None,
+        };
+        writer.write_table(&[group]).unwrap();
+        let zone = Location {
+            id: "z1".to_string(),
+            properties: Default::default(),
+            geometry: json!({"type": "Polygon", "coordinates": []}),
+        };
+        writer.write_locations(&[zone]).unwrap();
+        writer.finish().unwrap();
+
+        let mut store = GtfsZipStore::from_file(source.to_str().unwrap()).unwrap();
+        let mut writer = GtfsWriter::create(&copy).unwrap();
+        writer.write_store(&mut store).unwrap();
+        writer.finish().unwrap();
+
+        let mut store = GtfsZipStore::from_file(copy.to_str().unwrap()).unwrap();
+        let levels: Vec<Level> = store.read_all().unwrap();
+        assert_eq!(levels[0].level_name.as_deref(), Some("Platforms"));
+        assert_eq!(store.read_all::<LocationGroup>().unwrap().len(), 1);
+        assert_eq!(store.read_locations().unwrap()[0].id, "z1");
+        assert_eq!(
+            store.read_all::<StopTime>().unwrap().len(),
+            feed.stop_times.len()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
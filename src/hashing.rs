@@ -0,0 +1,9 @@
+/// Hash collections for internal lookups
+///
+/// Keys of these maps come from feed files and internal tables, not from
+/// untrusted network input, so a fast non-DoS-resistant hasher is used.
+use std::collections::{HashMap, HashSet};
+
+pub type FastHashMap<K, V> = HashMap<K, V, ahash::RandomState>;
+
+pub type FastHashSet<K> = HashSet<K, ahash::RandomState>;
@@ -11,21 +11,40 @@ use std::{
     time::{Duration, Instant},
 };
 
+#[cfg(feature = "es")]
 use base64::{
     engine::{general_purpose, GeneralPurpose},
     Engine,
 };
-use bigasstable::BigAssTable;
+use chrono::NaiveDate;
 use clap::builder::OsStr;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 use csv::{from_file, CsvTableReader};
 use datastore::Table;
-use gtfs::{GtfsCollection, GtfsZipStore, Pushable, TableFacory};
-use serde::Serialize;
-use xbus::{EsTrips, StationTimezoneGetter, TripsHit};
+use gtfs::accessibility::print_route_report;
+use gtfs::calendar::parse_gtfs_date;
+use gtfs::departures::{departure_board, print_departure_board};
+use gtfs::headsigns::infer_headsigns;
+use gtfs::stop_matching::{match_stops, read_stations, ExternalStation, MatchParams};
+use gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+use gtfs::validation::score::{quality_score, QualityScore};
+use gtfs::validation::{rules::RuleSet, validate_with_rules, ValidationInput, ValidationReport};
+use gtfs::{parse_gtfs_time, GtfsCollection, GtfsStore, GtfsZipStore, Pushable, TableFacory};
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "es")]
+use xbus::ndjson::NdjsonWriter;
+#[cfg(feature = "es")]
+use xbus::{EsTrips, EsTripsOptions, StationTimezoneGetter, TripsFilter, TripsHit};
 
 use anyhow::{bail, Context, Result};
 
-use masterdata::Masterdata;
+#[cfg(feature = "masterdata")]
+use masterdata::{Masterdata, MasterdataCache};
+use progress::ProgressMode;
+use retry::RetryPolicy;
+use stations::StationRegistry;
+use summary::RunStatus;
+use time::LocalTimePolicy;
 use zip::{read::ZipFile, ZipArchive};
 
 use crate::csv::CsvTableWriter;
@@ -34,20 +53,35 @@ mod datastore;
 
 mod gtfs;
 
+#[cfg(feature = "es")]
 mod xbus;
 
+#[cfg(feature = "masterdata")]
 mod masterdata;
 
+mod retry;
+
 mod csv;
 
-mod bigasstable;
+mod hashing;
 
-impl StationTimezoneGetter for Masterdata {
-    fn get_station_timezone(&self, station_code: &str) -> Option<&chrono_tz::Tz> {
-        self.get_station_timezone(station_code)
-    }
-}
+mod bench;
+
+mod geo;
+
+mod watch;
+
+mod stations;
+
+mod progress;
 
+mod summary;
+
+mod cancel;
+
+mod time;
+
+#[cfg(feature = "es")]
 fn decode_api_key(api_key: &str) -> anyhow::Result<(String, String)> {
     let bytes = general_purpose::STANDARD.decode(api_key)?;
 
@@ -65,11 +99,13 @@ fn decode_api_key(api_key: &str) -> anyhow::Result<(String, String)> {
     ));
 }
 
+#[cfg(feature = "es")]
 struct TripsConsumer {
     total_consumed: u64,
     next_print: u64,
 }
 
+#[cfg(feature = "es")]
 impl TripsConsumer {
     fn new() -> Self {
         TripsConsumer {
@@ -86,32 +122,94 @@ impl TripsConsumer {
     }
 }
 
-async fn download_connections() -> Result<()> {
-    let (api_id, api_key) =
-        decode_api_key("Rk1Uc2NJRUJ1LXY3Q2FoNFQ0eG06M0VvOWZ5ODdUcUM4X1gtVjNEZU1nUQ==")
-            .context("Invalid api key")?;
+/// Where to fetch trips from and where to write them
+#[cfg(feature = "es")]
+struct FetchParams {
+    url: String,
+    /// Index names or patterns
+    indices: Vec<String>,
+    masterdata_url: String,
+    masterdata_cache: Option<MasterdataCache>,
+    masterdata_timeout: Duration,
+    /// Local stations file used instead of masterdata
+    masterdata_file: Option<PathBuf>,
+    api_key: String,
+    retry: RetryPolicy,
+    options: EsTripsOptions,
+    /// Carriers to fetch, all carriers of the index if not given
+    carriers: Option<Vec<String>>,
+    filter: TripsFilter,
+    /// Number of carriers fetched at the same time
+    concurrency: usize,
+    output: PathBuf,
+}
 
-    let mut masterdata = Masterdata::new("http://master-data.prod.internal.distribusion.com");
+/// Stream trips of every carrier into a json lines file
+#[cfg(feature = "es")]
+async fn download_connections(params: &FetchParams) -> Result<()> {
+    let (api_id, api_key) = decode_api_key(&params.api_key).context("Invalid api key")?;
+
+    let mut masterdata = match &params.masterdata_file {
+        Some(path) => Masterdata::from_file(path),
+        None => Masterdata::new(&params.masterdata_url)
+            .with_timeout(params.masterdata_timeout)
+            .with_retry_policy(params.retry.clone()),
+    };
+    if let Some(cache) = &params.masterdata_cache {
+        masterdata = masterdata.with_cache(cache.clone());
+    }
 
     log::info!("Getting station timezones");
     masterdata.update_data().await?;
 
     let trips = EsTrips::new(
-        "https://prod-xbus.es.europe-west3.gcp.cloud.es.io",
-        "trips",
+        &params.url,
+        &params.indices,
         api_id.as_str(),
         api_key.as_str(),
-        masterdata,
+        StationRegistry::from_masterdata(&masterdata),
     )
-    .context("Could not connect to elasticsearch")?;
+    .context("Could not connect to elasticsearch")?
+    .with_retry_policy(params.retry.clone())
+    .with_options(params.options.clone());
 
+    let mut writer = NdjsonWriter::create(&params.output)?;
     let mut consumer = TripsConsumer::new();
 
-    trips
-        .consume_into("FBRA", |x| consumer.consume_next(x))
-        .await?;
+    let carriers = match &params.carriers {
+        Some(carriers) => carriers.clone(),
+        None => {
+            let carriers = trips.list_carriers().await?;
+            log::info!("Found {} carriers", carriers.len());
+            carriers.into_iter().map(|x| x.carrier).collect()
+        }
+    };
+
+    let mut write_error = None;
+
+    let fetched = trips
+        .consume_carriers_into(&carriers, &params.filter, params.concurrency, |_, hit| {
+            if write_error.is_none() {
+                write_error = writer.write(&hit).err();
+            }
+            consumer.consume_next(hit)
+        })
+        .await;
+
+    // Keep trips fetched before cancellation, the summary marks the output as partial
+    if fetched.is_err() && !cancel::is_cancelled() {
+        return fetched;
+    }
+
+    if let Some(err) = write_error {
+        return Err(err.context(format!("Could not write to {:?}", params.output)));
+    }
 
-    Ok(())
+    log::info!("Wrote {} trips", writer.written());
+    writer.finish()?;
+    summary::record_output(&params.output);
+
+    fetched
 }
 
 fn read_connections() {
@@ -132,7 +230,7 @@ fn read_connections() {
 fn write_connections<'a, I: IntoIterator<Item = &'a gtfs::Route>>(routes: I) {
     let mut writer: CsvTableWriter<gtfs::Route> = CsvTableWriter::new("connections.csv");
     for route in routes {
-        writer.write_row(route);
+        writer.write_row(route).unwrap();
     }
 }
 
@@ -238,30 +336,21 @@ fn write_connections<'a, I: IntoIterator<Item = &'a gtfs::Route>>(routes: I) {
 //     // mapping
 // }
 
-struct BigAssTableFactory {}
-
-impl<I> Pushable<I> for BigAssTable<I> {
-    fn push(&mut self, item: I) {
-        BigAssTable::push(self, item);
-    }
+/// Tables of a feed stored in temporary files
+struct DiskTableFactory {}
 
-    fn length(&self) -> usize {
-        BigAssTable::length(self)
-    }
-}
-
-impl TableFacory for BigAssTableFactory {
-    fn new<I: 'static>() -> Box<dyn gtfs::Pushable<I>> {
-        Box::new(BigAssTable::<I>::new())
+impl TableFacory for DiskTableFactory {
+    fn new<I: Serialize + DeserializeOwned + 'static>() -> Result<Box<dyn gtfs::Pushable<I>>> {
+        Ok(Box::new(Table::<I>::temporary()?))
     }
 }
 
 async fn async_main() -> Result<()> {
     let mut gtfs_store =
-        GtfsZipStore::from_file("/Users/artef/Downloads/ntra_import_latest_ntra-in.gtfs.txt.zip");
+        GtfsZipStore::from_file("/Users/artef/Downloads/ntra_import_latest_ntra-in.gtfs.txt.zip")?;
     // let mut gtfs_store = GtfsZipStore::from_file("/Users/artef/dev/dtfs/local/CATA.gtfs.txt.zip");
 
-    let gtfs_collection = GtfsCollection::from_store::<_, BigAssTableFactory>(&mut gtfs_store);
+    let gtfs_collection = GtfsCollection::from_store::<_, DiskTableFactory>(&mut gtfs_store);
 
     // read_zip("/Users/artef/dev/dtfs/local/CATA.gtfs.txt.zip");
 
@@ -289,17 +378,705 @@ async fn async_main() -> Result<()> {
     anyhow::Result::<()>::Ok(())
 }
 
+fn cli() -> Command {
+    let cli = Command::new("rdtfs")
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .global(true)
+                .value_parser(["auto", "always", "never", "log"])
+                .help("Progress bars, log lines or nothing, RDTFS_PROGRESS if not given"),
+        )
+        .arg(
+            Arg::new("memory-budget")
+                .long("memory-budget")
+                .global(true)
+                .value_parser(value_parser!(u64))
+                .help("Warn when resident memory gets close to this many MiB"),
+        )
+        .arg(
+            Arg::new("spill-dir")
+                .long("spill-dir")
+                .global(true)
+                .help(format!(
+                    "Directory of temporary tables, {} or the system temp dir if not given",
+                    datastore::SPILL_DIR_ENV
+                )),
+        )
+        .arg(
+            Arg::new("spill-reserve")
+                .long("spill-reserve")
+                .global(true)
+                .value_parser(value_parser!(u64))
+                .help("Fail instead of leaving fewer than this many MiB free in the spill dir"),
+        )
+        .arg(
+            Arg::new("summary")
+                .long("summary")
+                .global(true)
+                .help("Path of a json summary of the run written when it ends"),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check consistency of a gtfs feed")
+                .arg(Arg::new("feed").required(true).help("Path to gtfs zip"))
+                .arg(
+                    Arg::new("rules")
+                        .long("rules")
+                        .help("Json file disabling rules or overriding their severity"),
+                ),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Validate a gtfs feed again every time the file changes")
+                .arg(Arg::new("feed").required(true).help("Path to gtfs zip"))
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .required(true)
+                        .help("Path the latest validation report is published to"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_parser(value_parser!(u64))
+                        .default_value("60")
+                        .help("Seconds between checks of the feed file"),
+                )
+                .arg(
+                    Arg::new("rules")
+                        .long("rules")
+                        .help("Json file disabling rules or overriding their severity"),
+                ),
+        )
+        .subcommand(match_stops_command())
+        .subcommand(
+            Command::new("departures")
+                .about("Print departures from a stop on a date")
+                .arg(Arg::new("feed").required(true).help("Path to gtfs zip"))
+                .arg(Arg::new("stop").required(true).help("stop_id"))
+                .arg(
+                    Arg::new("date")
+                        .long("date")
+                        .required(true)
+                        .help("Date in YYYYMMDD format"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .default_value("00:00:00")
+                        .help("Start of the time window, HH:MM:SS"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .default_value("24:00:00")
+                        .help("End of the time window, HH:MM:SS"),
+                ),
+        )
+        .subcommand(
+            Command::new("routes")
+                .about("Print summary of every route")
+                .arg(Arg::new("feed").required(true).help("Path to gtfs zip")),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Time each pipeline stage on a gtfs feed")
+                .arg(
+                    Arg::new("feed")
+                        .required_unless_present("synthetic")
+                        .help("Path to gtfs zip"),
+                )
+                .arg(
+                    Arg::new("synthetic")
+                        .long("synthetic")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with("feed")
+                        .help("Benchmark a generated feed with given number of routes"),
+                )
+                .arg(
+                    Arg::new("runs")
+                        .long("runs")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1")
+                        .help("Number of runs, fastest time of each stage is reported"),
+                ),
+        );
+
+    #[cfg(feature = "es")]
+    let cli = cli.subcommand(fetch_es_command());
+
+    cli
+}
+
+/// Stations are read from a csv, or from masterdata with the masterdata feature
+fn match_stops_command() -> Command {
+    let stations = Arg::new("stations").help("Csv with id, name, lat and lon of external stations");
+
+    #[cfg(not(feature = "masterdata"))]
+    let stations = stations.required(true);
+    #[cfg(feature = "masterdata")]
+    let stations = stations.required_unless_present_any(["masterdata", "masterdata-file"]);
+
+    let command = Command::new("match-stops")
+        .about("Link gtfs stops to stations of an external registry")
+        .arg(Arg::new("feed").required(true).help("Path to gtfs zip"))
+        .arg(stations);
+
+    #[cfg(feature = "masterdata")]
+    let command = command
+        .arg(
+            Arg::new("masterdata")
+                .long("masterdata")
+                .conflicts_with_all(["stations", "masterdata-file"])
+                .help("Match to stations of this masterdata url instead of a csv"),
+        )
+        .arg(
+            Arg::new("masterdata-file")
+                .long("masterdata-file")
+                .conflicts_with("stations")
+                .help("Match to stations of a local masterdata stations file"),
+        );
+
+    command
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .required(true)
+                .help("Path of the csv mapping stop_id to external id"),
+        )
+        .arg(
+            Arg::new("max-distance")
+                .long("max-distance")
+                .value_parser(value_parser!(f64))
+                .default_value("300")
+                .help("Maximum distance in meters between a stop and its station"),
+        )
+}
+
+/// Subcommand downloading trips, only built with the es feature
+#[cfg(feature = "es")]
+fn fetch_es_command() -> Command {
+    Command::new("fetch-es")
+        .about("Download trips of carriers from xbus elasticsearch")
+        .arg(
+            Arg::new("carrier")
+                .long("carrier")
+                .required_unless_present("all-carriers")
+                .action(ArgAction::Append)
+                .help("Marketing carrier uid, may be repeated"),
+        )
+        .arg(
+            Arg::new("all-carriers")
+                .long("all-carriers")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("carrier")
+                .help("Fetch every carrier found in the index"),
+        )
+        .arg(Arg::new("output").long("output").required(true).help(
+            "Path of the json lines file trips are written to, gzip if ending with .gz",
+        ))
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .default_value("https://prod-xbus.es.europe-west3.gcp.cloud.es.io")
+                .help("Elasticsearch url"),
+        )
+        .arg(
+            Arg::new("index")
+                .long("index")
+                .action(ArgAction::Append)
+                .default_value("trips")
+                .help(
+                    "Elasticsearch index or pattern such as trips-2023-*, may be repeated",
+                ),
+        )
+        .arg(
+            Arg::new("allow-partial")
+                .long("allow-partial")
+                .action(ArgAction::SetTrue)
+                .help("Keep going when some indices are missing or their shards fail"),
+        )
+        .arg(
+            Arg::new("ambiguous-times")
+                .long("ambiguous-times")
+                .value_parser(["earliest", "latest", "reject"])
+                .default_value("reject")
+                .help("Reading of local times occurring twice when clocks are turned back"),
+        )
+        .arg(
+            Arg::new("skipped-times")
+                .long("skipped-times")
+                .value_parser(["shift", "reject"])
+                .default_value("reject")
+                .help("Reading of local times skipped when clocks are turned forward"),
+        )
+        .arg(
+            Arg::new("masterdata")
+                .long("masterdata")
+                .default_value("http://master-data.prod.internal.distribusion.com")
+                .help("Masterdata url used to get station timezones"),
+        )
+        .arg(
+            Arg::new("masterdata-file")
+                .long("masterdata-file")
+                .conflicts_with("masterdata-cache")
+                .help("Stations json or csv with code, time_zone, latitude and longitude used instead of masterdata"),
+        )
+        .arg(
+            Arg::new("masterdata-timeout")
+                .long("masterdata-timeout")
+                .value_parser(value_parser!(u64))
+                .default_value("30")
+                .help("Seconds before a masterdata request is given up and retried"),
+        )
+        .arg(Arg::new("masterdata-cache").long("masterdata-cache").help(
+            "File caching masterdata stations, also used when masterdata is unreachable",
+        ))
+        .arg(
+            Arg::new("masterdata-ttl")
+                .long("masterdata-ttl")
+                .value_parser(value_parser!(u64))
+                .default_value("24")
+                .help("Hours after which cached stations are fetched again"),
+        )
+        .arg(
+            Arg::new("refresh-masterdata")
+                .long("refresh-masterdata")
+                .action(ArgAction::SetTrue)
+                .requires("masterdata-cache")
+                .help("Fetch stations even if the cache is fresh"),
+        )
+        .arg(
+            Arg::new("from-date")
+                .long("from-date")
+                .help("First departure date to fetch, YYYY-MM-DD"),
+        )
+        .arg(
+            Arg::new("to-date")
+                .long("to-date")
+                .help("Last departure date to fetch, YYYY-MM-DD"),
+        )
+        .arg(
+            Arg::new("departure-station")
+                .long("departure-station")
+                .action(ArgAction::Append)
+                .help("Only trips departing from this station uid, may be repeated"),
+        )
+        .arg(
+            Arg::new("arrival-station")
+                .long("arrival-station")
+                .action(ArgAction::Append)
+                .help("Only trips arriving at this station uid, may be repeated"),
+        )
+        .arg(
+            Arg::new("booked-out")
+                .long("booked-out")
+                .value_parser(value_parser!(bool))
+                .help("Only trips which are or are not booked out"),
+        )
+        .arg(
+            Arg::new("concurrency")
+                .long("concurrency")
+                .value_parser(value_parser!(usize))
+                .default_value("4")
+                .help("Number of carriers fetched at the same time"),
+        )
+        .arg(
+            Arg::new("page-size")
+                .long("page-size")
+                .value_parser(value_parser!(i64))
+                .default_value("100")
+                .help("Number of trips requested at once"),
+        )
+        .arg(
+            Arg::new("exclude-field")
+                .long("exclude-field")
+                .action(ArgAction::Append)
+                .help("Optional _source field not to download, may be repeated"),
+        )
+        .arg(
+            Arg::new("max-retries")
+                .long("max-retries")
+                .value_parser(value_parser!(u32))
+                .default_value("5")
+                .help("Retries of requests failing with transient errors"),
+        )
+        .arg(
+            Arg::new("max-rate")
+                .long("max-rate")
+                .value_parser(value_parser!(f64))
+                .help("Maximum number of requests per second"),
+        )
+        .arg(
+            Arg::new("api-key")
+                .long("api-key")
+                .help("Base64 encoded '<id>:<key>', read from XBUS_API_KEY if not set"),
+        )
+}
+
+/// Open a gtfs zip, recording it as input of the run summary
+fn open_feed(feed: &str) -> Result<GtfsZipStore> {
+    let store = GtfsZipStore::from_file(feed)?;
+    summary::record_feed(feed)?;
+    Ok(store)
+}
+
+fn load_rules(args: &ArgMatches) -> Result<RuleSet> {
+    match args.get_one::<String>("rules") {
+        Some(path) => RuleSet::from_file(path),
+        None => Ok(RuleSet::default()),
+    }
+}
+
+fn validate_feed(feed: &str, rules: &RuleSet) -> Result<(ValidationReport, QualityScore)> {
+    let mut gtfs_store = open_feed(feed)?;
+    let input = ValidationInput::from_store(&mut gtfs_store).context("Could not read feed")?;
+    let report = validate_with_rules(&input, rules);
+    let score = quality_score(&input, &report);
+    Ok((report, score))
+}
+
+fn run_validate(args: &ArgMatches) -> Result<()> {
+    let feed = args.get_one::<String>("feed").unwrap();
+    let rules = load_rules(args)?;
+
+    let (report, score) = validate_feed(feed, &rules)?;
+    report.print();
+    score.print();
+    summary::record_report(&report);
+
+    if let Some(severity) = report.max_severity() {
+        // Exiting skips the summary written by main
+        summary::finish(RunStatus::Ok, None)?;
+        std::process::exit(severity.exit_code());
+    }
+
+    Ok(())
+}
+
+fn run_watch(args: &ArgMatches) -> Result<()> {
+    let feed = args.get_one::<String>("feed").unwrap();
+    let output = args.get_one::<String>("output").unwrap();
+    let interval = Duration::from_secs(*args.get_one::<u64>("interval").unwrap());
+    let rules = load_rules(args)?;
+
+    let mut watcher = watch::FeedWatcher::new(feed);
+
+    loop {
+        match watcher.poll() {
+            Ok(true) => {
+                log::info!("New version of {feed}, validating");
+                // Keep the previous report published if the new version can not be processed
+                match validate_feed(feed, &rules) {
+                    Ok((report, score)) => {
+                        log::info!("Quality score {:.1}", score.total());
+                        watch::write_atomically(output, &report.to_string())?;
+                        summary::record_report(&report);
+                        summary::record_output(output);
+                        log::info!("Published report to {output}");
+                    }
+                    Err(err) => log::error!("Could not validate {feed}: {err:#}"),
+                }
+            }
+            Ok(false) => (),
+            Err(err) => log::warn!("{err:#}"),
+        }
+        cancel::sleep(interval)?;
+    }
+}
+
+/// Stations of the masterdata given to match-stops, none if matching against a csv
+#[cfg(feature = "masterdata")]
+fn masterdata_stations(args: &ArgMatches) -> Result<Option<Vec<ExternalStation>>> {
+    let mut masterdata = match (
+        args.get_one::<String>("masterdata"),
+        args.get_one::<String>("masterdata-file"),
+    ) {
+        (Some(url), _) => Masterdata::new(url),
+        (None, Some(path)) => Masterdata::from_file(path),
+        (None, None) => return Ok(None),
+    };
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(masterdata.update_data())?;
+
+    Ok(Some(
+        StationRegistry::from_masterdata(&masterdata).external_stations(),
+    ))
+}
+
+#[cfg(not(feature = "masterdata"))]
+fn masterdata_stations(args: &ArgMatches) -> Result<Option<Vec<ExternalStation>>> {
+    Ok(None)
+}
+
+fn run_match_stops(args: &ArgMatches) -> Result<()> {
+    let feed = args.get_one::<String>("feed").unwrap();
+    let output = args.get_one::<String>("output").unwrap();
+    let params = MatchParams {
+        max_distance_m: *args.get_one::<f64>("max-distance").unwrap(),
+        ..Default::default()
+    };
+
+    let mut gtfs_store = open_feed(feed)?;
+    let stops: Vec<gtfs::Stop> = gtfs_store.read_all()?;
+
+    let stations = match masterdata_stations(args)? {
+        Some(stations) => stations,
+        None => {
+            let stations = args.get_one::<String>("stations").unwrap();
+            let file =
+                File::open(stations).with_context(|| format!("Could not open {stations}"))?;
+            read_stations(BufReader::new(file))?
+        }
+    };
+
+    let matches = match_stops(&stops, &stations, &params);
+    log::info!("Matched {} of {} stops", matches.len(), stops.len());
+
+    let text = csv::to_csv_text(&matches).unwrap_or_default();
+    watch::write_atomically(output, &text)?;
+    summary::record_output(output);
+
+    Ok(())
+}
+
+fn run_departures(args: &ArgMatches) -> Result<()> {
+    let feed = args.get_one::<String>("feed").unwrap();
+    let stop_id = args.get_one::<String>("stop").unwrap();
+    let date = parse_gtfs_date(args.get_one::<String>("date").unwrap())?;
+    let from = parse_gtfs_time(args.get_one::<String>("from").unwrap())?;
+    let to = parse_gtfs_time(args.get_one::<String>("to").unwrap())?;
+
+    let mut gtfs_store = open_feed(feed)?;
+    let routes: Vec<gtfs::Route> = gtfs_store.read_all()?;
+    let mut trips: Vec<gtfs::Trip> = gtfs_store.read_all()?;
+    let stops: Vec<gtfs::Stop> = gtfs_store.read_all()?;
+    let stop_times: Vec<gtfs::StopTime> = gtfs_store.read_all()?;
+    let calendars: Vec<gtfs::Calendar> = gtfs_store.try_read_all()?;
+    let calendar_dates: Vec<gtfs::CalendarDate> = gtfs_store.try_read_all()?;
+
+    let inferred = infer_headsigns(&mut trips, &stops, &stop_times);
+    log::info!("Inferred {} missing headsigns", inferred.len());
+
+    let (board, errors) = departure_board(
+        stop_id,
+        date,
+        from,
+        to,
+        &routes,
+        &trips,
+        &stop_times,
+        &calendars,
+        &calendar_dates,
+    );
+    for errors in &errors {
+        errors.log();
+        summary::record_errors("malformed_row", errors.count);
+    }
+    print_departure_board(&board, &inferred);
+
+    Ok(())
+}
+
+#[cfg(feature = "es")]
+fn run_fetch_es(args: &ArgMatches) -> Result<()> {
+    let api_key = match args.get_one::<String>("api-key") {
+        Some(value) => value.clone(),
+        None => std::env::var("XBUS_API_KEY")
+            .context("Api key not given, pass --api-key or set XBUS_API_KEY")?,
+    };
+
+    let retry = RetryPolicy {
+        max_retries: *args.get_one::<u32>("max-retries").unwrap(),
+        min_interval: args
+            .get_one::<f64>("max-rate")
+            .filter(|x| **x > 0.0)
+            .map(|x| Duration::from_secs_f64(1.0 / x))
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let parse_date = |name: &str| {
+        args.get_one::<String>(name)
+            .map(|x| NaiveDate::parse_from_str(x, "%Y-%m-%d"))
+            .transpose()
+            .with_context(|| format!("--{name} must be a YYYY-MM-DD date"))
+    };
+    let stations = |name: &str| {
+        args.get_many::<String>(name)
+            .map(|x| x.cloned().collect())
+            .unwrap_or_default()
+    };
+
+    let filter = TripsFilter {
+        departure_date_from: parse_date("from-date")?,
+        departure_date_to: parse_date("to-date")?,
+        departure_stations: stations("departure-station"),
+        arrival_stations: stations("arrival-station"),
+        booked_out: args.get_one::<bool>("booked-out").copied(),
+    };
+
+    let options = EsTripsOptions {
+        page_size: *args.get_one::<i64>("page-size").unwrap(),
+        source_excludes: args
+            .get_many::<String>("exclude-field")
+            .map(|x| x.cloned().collect())
+            .unwrap_or_default(),
+        allow_partial_results: args.get_flag("allow-partial"),
+        local_time_policy: LocalTimePolicy {
+            ambiguous: args.get_one::<String>("ambiguous-times").unwrap().parse()?,
+            nonexistent: args.get_one::<String>("skipped-times").unwrap().parse()?,
+        },
+        ..Default::default()
+    };
+
+    let params = FetchParams {
+        url: args.get_one::<String>("url").unwrap().clone(),
+        indices: args.get_many::<String>("index").unwrap().cloned().collect(),
+        masterdata_url: args.get_one::<String>("masterdata").unwrap().clone(),
+        masterdata_timeout: Duration::from_secs(
+            *args.get_one::<u64>("masterdata-timeout").unwrap(),
+        ),
+        masterdata_file: args.get_one::<String>("masterdata-file").map(PathBuf::from),
+        masterdata_cache: args
+            .get_one::<String>("masterdata-cache")
+            .map(|path| MasterdataCache {
+                path: PathBuf::from(path),
+                ttl: Duration::from_secs(3600 * args.get_one::<u64>("masterdata-ttl").unwrap()),
+                force_refresh: args.get_flag("refresh-masterdata"),
+            }),
+        api_key,
+        retry,
+        options,
+        carriers: args
+            .get_many::<String>("carrier")
+            .map(|x| x.cloned().collect()),
+        filter,
+        concurrency: *args.get_one::<usize>("concurrency").unwrap(),
+        output: PathBuf::from(args.get_one::<String>("output").unwrap()),
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(download_connections(&params))
+}
+
+fn run_routes(args: &ArgMatches) -> Result<()> {
+    let feed = args.get_one::<String>("feed").unwrap();
+    let mut gtfs_store = open_feed(feed)?;
+
+    let routes: Vec<gtfs::Route> = gtfs_store.read_all()?;
+    let trips: Vec<gtfs::Trip> = gtfs_store.read_all()?;
+
+    print_route_report(&routes, &trips);
+
+    Ok(())
+}
+
+fn run_bench(args: &ArgMatches) -> Result<()> {
+    let runs = *args.get_one::<usize>("runs").unwrap();
+
+    let mut results = Vec::new();
+
+    for run in 0..runs {
+        log::info!("Benchmark run {}/{}", run + 1, runs);
+        if let Some(routes) = args.get_one::<usize>("synthetic") {
+            let params = SyntheticFeedParams {
+                routes: *routes,
+                ..Default::default()
+            };
+            let mut gtfs_store = SyntheticFeed::generate(&params);
+            results.push(bench::bench_parse::<_, DiskTableFactory>(&mut gtfs_store)?);
+        } else {
+            let feed = args.get_one::<String>("feed").unwrap();
+            let mut gtfs_store = open_feed(feed)?;
+            results.push(bench::bench_parse::<_, DiskTableFactory>(&mut gtfs_store)?);
+        }
+    }
+
+    bench::print_timings(&bench::best_of(results));
+
+    Ok(())
+}
+
 fn main() -> Result<()> {
     env_logger::init_from_env(
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
     );
 
+    let matches = cli().get_matches();
+
+    let progress_mode = match matches.get_one::<String>("progress") {
+        Some(mode) => mode.parse()?,
+        None => match std::env::var(progress::PROGRESS_ENV) {
+            Ok(mode) => mode
+                .parse()
+                .with_context(|| format!("Invalid {}", progress::PROGRESS_ENV))?,
+            Err(_) => ProgressMode::Auto,
+        },
+    };
+    progress::init(progress_mode);
+    if let Some(budget) = matches.get_one::<u64>("memory-budget") {
+        progress::set_memory_budget(budget * 1024 * 1024);
+    }
+    let spill_dir = match matches.get_one::<String>("spill-dir") {
+        Some(dir) => Some(dir.clone()),
+        None => std::env::var(datastore::SPILL_DIR_ENV).ok(),
+    };
+    if let Some(dir) = spill_dir {
+        datastore::set_spill_dir(dir)?;
+    }
+    if let Some(reserve) = matches.get_one::<u64>("spill-reserve") {
+        datastore::set_spill_reserve(reserve * 1024 * 1024);
+    }
+
+    if let Some(path) = matches.get_one::<String>("summary") {
+        summary::start(path, matches.subcommand_name().unwrap_or("rdtfs"));
+    }
+
+    cancel::install_handler();
+
+    let result = run_command(&matches);
+    let status = match result {
+        Ok(()) => RunStatus::Ok,
+        Err(_) if cancel::is_cancelled() => RunStatus::Cancelled,
+        Err(_) => RunStatus::Failed,
+    };
+    summary::finish(status, result.as_ref().err().map(|x| format!("{x:#}")))?;
+
+    if status == RunStatus::Cancelled {
+        log::warn!("Cancelled, outputs only hold work completed until then");
+        std::process::exit(130);
+    }
+
+    result
+}
+
+fn run_command(matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("validate", args)) => return run_validate(args),
+        Some(("watch", args)) => return run_watch(args),
+        Some(("match-stops", args)) => return run_match_stops(args),
+        Some(("departures", args)) => return run_departures(args),
+        #[cfg(feature = "es")]
+        Some(("fetch-es", args)) => return run_fetch_es(args),
+        Some(("routes", args)) => return run_routes(args),
+        Some(("bench", args)) => return run_bench(args),
+        _ => (),
+    }
+
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .unwrap();
 
-    runtime.block_on(async { async_main().await })?;
-
-    Ok(())
+    runtime.block_on(async { async_main().await })
 }
@@ -1,17 +1,102 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
-use serde::Deserialize;
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::csv::CsvTableReader;
+use crate::gtfs::stop_matching::ExternalStation;
+use crate::retry::RetryPolicy;
+use crate::watch::write_atomically;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct Masterdata {
     client: reqwest::Client,
+    /// Keyed by codes of an http response, so not a FastHashMap
     station_timezones: HashMap<String, chrono_tz::Tz>,
+    /// Named stations with coordinates, used to match gtfs stops
+    stations: Vec<ExternalStation>,
     stations_url: String,
+    cache: Option<MasterdataCache>,
+    retry: RetryPolicy,
+    /// Local stations file used instead of the api
+    stations_file: Option<PathBuf>,
 }
 
-#[derive(Deserialize)]
+/// Local copy of the station list, used while fresh and when masterdata is unreachable
+#[derive(Clone)]
+pub struct MasterdataCache {
+    pub path: PathBuf,
+    /// Age after which stations are fetched again
+    pub ttl: Duration,
+    /// Fetch stations even if the cache is fresh
+    pub force_refresh: bool,
+}
+
+impl MasterdataCache {
+    /// Stations of the cache if it exists and is younger than max_age
+    fn read(&self, max_age: Option<Duration>) -> Result<Option<Vec<Station>>> {
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return Ok(None);
+        };
+        let age = SystemTime::now()
+            .duration_since(metadata.modified()?)
+            .unwrap_or_default();
+        if max_age.is_some_and(|x| age > x) {
+            return Ok(None);
+        }
+
+        let text = fs::read_to_string(&self.path)
+            .with_context(|| format!("Could not read {:?}", self.path))?;
+        let stations = serde_json::from_str(&text)
+            .with_context(|| format!("Could not parse masterdata cache {:?}", self.path))?;
+        Ok(Some(stations))
+    }
+
+    fn write(&self, stations: &[Station]) -> Result<()> {
+        write_atomically(&self.path, &serde_json::to_string(stations)?)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 struct Station {
     code: String,
     time_zone: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+}
+
+/// Stations of a json file in the cache format or of a csv file
+///
+/// Csv files have code, time_zone, name, latitude and longitude columns.
+fn read_stations_file(path: &Path) -> Result<Vec<Station>> {
+    let file = File::open(path).with_context(|| format!("Could not open {path:?}"))?;
+    let reader = BufReader::new(file);
+
+    if path.extension().is_some_and(|x| x == "csv") {
+        let mut reader = CsvTableReader::new(reader)?;
+        let mut buf = String::new();
+        let mut field_buf = Vec::new();
+        let mut stations = Vec::new();
+
+        while let Some(station) = reader.read::<Station>(&mut field_buf, &mut buf)? {
+            stations.push(station);
+        }
+        Ok(stations)
+    } else {
+        serde_json::from_reader(reader).with_context(|| format!("Could not parse {path:?}"))
+    }
 }
 
 #[derive(Deserialize)]
@@ -19,38 +104,204 @@ struct StationWrapper {
     attributes: Station,
 }
 
+#[derive(Deserialize, Default)]
+struct Links {
+    next: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Meta {
+    /// Number of stations over all pages
+    total: Option<usize>,
+}
+
 #[derive(Deserialize)]
 
 struct MastedataResponse {
     data: Vec<StationWrapper>,
+    #[serde(default)]
+    links: Links,
+    #[serde(default)]
+    meta: Meta,
+}
+
+impl MastedataResponse {
+    /// Absolute url of the next page, links may be relative to the current page
+    fn next_page(&self, url: &Url) -> Result<Option<Url>> {
+        let Some(next) = &self.links.next else {
+            return Ok(None);
+        };
+        let next = url
+            .join(next)
+            .with_context(|| format!("Invalid next page link {next}"))?;
+        if &next == url {
+            bail!("Next page link of {url} points to itself");
+        }
+        Ok(Some(next))
+    }
 }
 
 impl Masterdata {
     pub fn new(masterdata_url: &str) -> Self {
         Masterdata {
-            client: reqwest::Client::new(),
+            client: make_client(DEFAULT_TIMEOUT),
             station_timezones: HashMap::new(),
+            stations: Vec::new(),
             stations_url: format!("{masterdata_url}/api/v1/stations"),
+            cache: None,
+            retry: RetryPolicy::default(),
+            stations_file: None,
         }
     }
 
-    pub async fn update_data(&mut self) -> anyhow::Result<()> {
-        let response = self
-            .client
-            .get(&self.stations_url)
+    /// Masterdata read from a local stations file, no requests are made
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        Masterdata {
+            stations_file: Some(path.as_ref().to_path_buf()),
+            ..Self::new("")
+        }
+    }
+
+    /// Time limit of a single request including reading the response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = make_client(timeout);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: MasterdataCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    async fn try_fetch_page(&self, url: &Url) -> reqwest::Result<MastedataResponse> {
+        self.client
+            .get(url.clone())
             .send()
             .await?
+            .error_for_status()?
             .json::<MastedataResponse>()
-            .await?;
+            .await
+    }
+
+    /// Fetch a page of stations, retrying timeouts, connection errors and server errors
+    async fn fetch_page(&self, url: &Url) -> Result<MastedataResponse> {
+        let mut attempt = 0;
+
+        loop {
+            let err = match self.try_fetch_page(url).await {
+                Ok(response) => return Ok(response),
+                Err(err) if is_transient(&err) => err,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Unexpected response of {url}"))
+                }
+            };
+
+            if attempt >= self.retry.max_retries {
+                bail!(
+                    "Masterdata service down, {} failed after {} retries: {}",
+                    url,
+                    attempt,
+                    err
+                );
+            }
+
+            let delay = self.retry.backoff(attempt);
+            attempt += 1;
+            log::warn!("Masterdata request failed with {err}, retry {attempt} in {delay:.1?}");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Fetch stations of all pages, checking the count against the reported total
+    async fn fetch_stations(&self) -> Result<Vec<Station>> {
+        let mut url = Some(
+            Url::parse(&self.stations_url)
+                .with_context(|| format!("Invalid masterdata url {}", self.stations_url))?,
+        );
+        let mut stations = Vec::new();
+        let mut total = None;
+
+        while let Some(page_url) = url {
+            let page = self.fetch_page(&page_url).await?;
+            url = page.next_page(&page_url)?;
+            total = page.meta.total.or(total);
+            stations.extend(page.data.into_iter().map(|x| x.attributes));
+        }
+
+        if let Some(total) = total {
+            if stations.len() != total {
+                bail!(
+                    "Masterdata reported {} stations but {} were returned",
+                    total,
+                    stations.len()
+                );
+            }
+        }
+
+        Ok(stations)
+    }
+
+    /// Stations from a local file, a fresh cache, masterdata or a stale cache, in this order
+    async fn load_stations(&self) -> Result<Vec<Station>> {
+        if let Some(path) = &self.stations_file {
+            return read_stations_file(path);
+        }
+        let Some(cache) = &self.cache else {
+            return self.fetch_stations().await;
+        };
 
-        for station in response.data {
-            let tz_parsed = match station.attributes.time_zone.parse() {
+        if !cache.force_refresh {
+            if let Some(stations) = cache.read(Some(cache.ttl))? {
+                log::info!("Using cached stations of {:?}", cache.path);
+                return Ok(stations);
+            }
+        }
+
+        match self.fetch_stations().await {
+            Ok(stations) => {
+                if let Err(err) = cache.write(&stations) {
+                    log::warn!("Could not cache stations: {err:#}");
+                }
+                Ok(stations)
+            }
+            Err(err) => match cache.read(None)? {
+                Some(stations) => {
+                    log::warn!("Masterdata unreachable, using stale cache: {err:#}");
+                    Ok(stations)
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    #[tracing::instrument(name = "masterdata", skip(self), fields(stations))]
+    pub async fn update_data(&mut self) -> anyhow::Result<()> {
+        let stations = self.load_stations().await?;
+        tracing::Span::current().record("stations", stations.len());
+
+        for station in stations {
+            if let (Some(name), Some(lat), Some(lon)) =
+                (&station.name, station.latitude, station.longitude)
+            {
+                self.stations.push(ExternalStation {
+                    id: station.code.clone(),
+                    name: name.clone(),
+                    lat,
+                    lon,
+                });
+            }
+
+            let tz_parsed = match station.time_zone.parse() {
                 Ok(val) => val,
                 Err(_) => continue,
             };
 
-            self.station_timezones
-                .insert(station.attributes.code, tz_parsed);
+            self.station_timezones.insert(station.code, tz_parsed);
         }
 
         Ok(())
@@ -59,4 +310,132 @@ impl Masterdata {
     pub fn get_station_timezone(&self, code: &str) -> Option<&chrono_tz::Tz> {
         self.station_timezones.get(code)
     }
+
+    /// Timezones of all stations by code
+    pub fn station_timezones(&self) -> impl Iterator<Item = (&str, &chrono_tz::Tz)> {
+        self.station_timezones.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Stations having a name and coordinates with their code as id
+    pub fn stations(&self) -> &[ExternalStation] {
+        &self.stations
+    }
+}
+
+fn make_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("Could not build masterdata http client")
+}
+
+/// Errors of an unreachable, overloaded or restarting service
+fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_timeout()
+        || err.is_connect()
+        || err
+            .status()
+            .is_some_and(|x| x.is_server_error() || x.as_u16() == 429)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_page() {
+        let url = Url::parse("http://masterdata/api/v1/stations?page[number]=1").unwrap();
+        let page = |next: Option<&str>| MastedataResponse {
+            data: Vec::new(),
+            links: Links {
+                next: next.map(|x| x.to_string()),
+            },
+            meta: Meta::default(),
+        };
+
+        assert!(page(None).next_page(&url).unwrap().is_none());
+        assert_eq!(
+            page(Some("/api/v1/stations?page[number]=2"))
+                .next_page(&url)
+                .unwrap()
+                .unwrap()
+                .as_str(),
+            "http://masterdata/api/v1/stations?page[number]=2"
+        );
+        assert!(page(Some(url.as_str())).next_page(&url).is_err());
+    }
+
+    #[test]
+    fn test_from_file() {
+        let dir = std::env::temp_dir().join(format!("rdtfs-stations-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let csv = dir.join("stations.csv");
+        fs::write(
+            &csv,
+            "code,time_zone,name,latitude,longitude\n\
+             DEBERZOB,Europe/Berlin,Berlin ZOB,52.52,13.36\n\
+             GBLONVIC,Europe/London,,,\n",
+        )
+        .unwrap();
+        let json = dir.join("stations.json");
+        fs::write(
+            &json,
+            r#"[{"code":"GBLONVIC","time_zone":"Europe/London"}]"#,
+        )
+        .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for (path, stations) in [(csv, 1), (json, 0)] {
+            let mut masterdata = Masterdata::from_file(path);
+            runtime.block_on(masterdata.update_data()).unwrap();
+            assert_eq!(
+                masterdata.get_station_timezone("GBLONVIC"),
+                Some(&chrono_tz::Europe::London)
+            );
+            assert_eq!(masterdata.stations().len(), stations);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_cache_when_unreachable() {
+        let dir = std::env::temp_dir().join(format!("rdtfs-masterdata-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stations.json");
+        fs::write(
+            &path,
+            r#"[{"code":"DEBERZOB","time_zone":"Europe/Berlin"}]"#,
+        )
+        .unwrap();
+
+        // Nothing listens on the discard port
+        let mut masterdata = Masterdata::new("http://127.0.0.1:9")
+            .with_retry_policy(RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            })
+            .with_cache(MasterdataCache {
+                path,
+                ttl: Duration::ZERO,
+                force_refresh: true,
+            });
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(masterdata.update_data())
+            .unwrap();
+
+        assert_eq!(
+            masterdata.get_station_timezone("DEBERZOB"),
+            Some(&chrono_tz::Europe::Berlin)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,385 @@
+/// Progress reporting of pipeline stages, drawn as bars on one terminal by default
+///
+use std::{
+    io::IsTerminal,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use indicatif::{
+    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+
+use crate::hashing::FastHashMap;
+
+/// Environment variable selecting the progress mode when no flag is given
+pub const PROGRESS_ENV: &str = "RDTFS_PROGRESS";
+
+/// Time between two progress log lines of a stage in log mode
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Share of the memory budget above which a warning is logged
+const BUDGET_WARNING: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressMode {
+    /// Bars on a terminal, log lines otherwise
+    #[default]
+    Auto,
+    Always,
+    Never,
+    /// Periodic log lines instead of bars
+    Log,
+}
+
+impl FromStr for ProgressMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => ProgressMode::Auto,
+            "always" => ProgressMode::Always,
+            "never" => ProgressMode::Never,
+            "log" => ProgressMode::Log,
+            _ => bail!("Unknown progress mode {s}, expected auto, always, never or log"),
+        })
+    }
+}
+
+impl ProgressMode {
+    /// Auto resolved to bars or log lines depending on stderr
+    fn resolve(self) -> Self {
+        match self {
+            ProgressMode::Auto if std::io::stderr().is_terminal() => ProgressMode::Always,
+            ProgressMode::Auto => ProgressMode::Log,
+            mode => mode,
+        }
+    }
+}
+
+static MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+static MEMORY_BUDGET: OnceLock<u64> = OnceLock::new();
+
+/// Warn once resident memory gets close to the given number of bytes
+pub fn set_memory_budget(bytes: u64) {
+    let _ = MEMORY_BUDGET.set(bytes);
+}
+
+/// Field of /proc/self/status in bytes, none on other platforms
+fn proc_status_bytes(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|x| x.starts_with(field))?;
+    let kib: u64 = line[field.len()..]
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Current resident memory of the process
+fn resident_memory() -> Option<u64> {
+    proc_status_bytes("VmRSS:")
+}
+
+/// Highest resident memory of the process so far
+fn peak_resident_memory() -> Option<u64> {
+    proc_status_bytes("VmHWM:")
+}
+
+fn format_memory(bytes: Option<u64>) -> String {
+    bytes.map_or("unknown".to_string(), |x| HumanBytes(x).to_string())
+}
+
+/// Receiver of progress events, stages are identified by name
+pub trait ProgressSink: Send + Sync {
+    /// A stage processing total_bytes started, a running stage of the same name is replaced
+    fn on_stage(&self, stage: &str, total_bytes: u64);
+    fn on_bytes(&self, stage: &str, bytes: u64);
+    fn on_items(&self, stage: &str, items: u64);
+    fn on_stage_end(&self, stage: &str) {}
+}
+
+/// Sink ignoring all progress
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn on_stage(&self, stage: &str, total_bytes: u64) {}
+    fn on_bytes(&self, stage: &str, bytes: u64) {}
+    fn on_items(&self, stage: &str, items: u64) {}
+}
+
+/// Set the mode of all progress created afterwards, only the first call has an effect
+pub fn init(mode: ProgressMode) {
+    let _ = MODE.set(mode);
+}
+
+/// Work and resources of a finished stage
+#[derive(Debug, Clone)]
+pub struct StageSummary {
+    pub name: String,
+    pub bytes: u64,
+    pub rows: u64,
+    pub elapsed: Duration,
+    /// Highest resident memory sampled while the stage ran
+    pub peak_memory: Option<u64>,
+}
+
+struct Stages {
+    multi: MultiProgress,
+    mode: ProgressMode,
+    /// Stages in order of completion, including replaced ones
+    finished: Mutex<Vec<StageSummary>>,
+    running: Mutex<FastHashMap<String, Stage>>,
+    started: Instant,
+    budget_warned: AtomicBool,
+}
+
+impl Stages {
+    /// Sample resident memory into the stage and warn once when close to the budget
+    fn sample_memory(&self, stage: &mut Stage) {
+        let Some(memory) = resident_memory() else {
+            return;
+        };
+        stage.peak_memory = stage.peak_memory.max(Some(memory));
+
+        let Some(&budget) = MEMORY_BUDGET.get() else {
+            return;
+        };
+        if memory as f64 >= budget as f64 * BUDGET_WARNING
+            && !self.budget_warned.swap(true, Ordering::Relaxed)
+        {
+            log::warn!(
+                "{}: resident memory {} is close to the budget of {}",
+                stage.bar.prefix(),
+                HumanBytes(memory),
+                HumanBytes(budget)
+            );
+        }
+    }
+
+    fn finish(&self, mut stage: Stage) {
+        self.sample_memory(&mut stage);
+        let summary = stage.finish();
+        crate::summary::record_stage(&summary);
+        self.finished.lock().unwrap().push(summary);
+    }
+}
+
+impl Drop for Stages {
+    /// Summary of all stages once the pipeline owning the progress is done
+    fn drop(&mut self) {
+        let running: Vec<_> = self
+            .running
+            .get_mut()
+            .unwrap()
+            .drain()
+            .map(|x| x.1)
+            .collect();
+        for stage in running {
+            self.finish(stage);
+        }
+
+        let finished = self.finished.get_mut().unwrap();
+        if finished.is_empty() || self.mode == ProgressMode::Never {
+            return;
+        }
+        let bytes: u64 = finished.iter().map(|x| x.bytes).sum();
+        log::info!(
+            "Finished {} stages, read {} in {:.1?}, peak memory {}",
+            finished.len(),
+            HumanBytes(bytes),
+            self.started.elapsed(),
+            format_memory(peak_resident_memory())
+        );
+        for stage in finished.iter() {
+            log::info!(
+                "{}: {} rows, {} in {:.1?}, peak memory {}",
+                stage.name,
+                stage.rows,
+                HumanBytes(stage.bytes),
+                stage.elapsed,
+                format_memory(stage.peak_memory)
+            );
+        }
+        if let Some(largest) = finished.iter().max_by_key(|x| x.rows) {
+            log::info!("Largest stage {} with {} rows", largest.name, largest.rows);
+        }
+    }
+}
+
+/// Progress sink drawing stages with indicatif, clones draw into the same set of bars
+#[derive(Clone)]
+pub struct Progress {
+    stages: Arc<Stages>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Progress::new(MODE.get().copied().unwrap_or_default())
+    }
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode) -> Self {
+        let mode = mode.resolve();
+        let multi = match mode {
+            ProgressMode::Always => MultiProgress::new(),
+            _ => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        };
+
+        Progress {
+            stages: Arc::new(Stages {
+                multi,
+                mode,
+                finished: Mutex::new(Vec::new()),
+                running: Mutex::new(FastHashMap::default()),
+                started: Instant::now(),
+                budget_warned: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    fn stage(&self, name: &str, total: u64) -> Stage {
+        let bar = self.stages.multi.add(ProgressBar::new(total));
+
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{prefix:>20} {bar:40.cyan/blue} {bytes:>7}/{total_bytes:7} {binary_bytes_per_sec} [ETA: {eta}] {msg}",
+            )
+            .unwrap()
+            .progress_chars("##-"),
+        );
+        bar.set_prefix(name.to_string());
+
+        Stage {
+            bar,
+            items: 0,
+            peak_memory: None,
+            log_interval: (self.stages.mode == ProgressMode::Log).then_some(LOG_INTERVAL),
+            last_log: Instant::now(),
+        }
+    }
+}
+
+impl ProgressSink for Progress {
+    fn on_stage(&self, stage: &str, total_bytes: u64) {
+        let new = self.stage(stage, total_bytes);
+        let mut running = self.stages.running.lock().unwrap();
+        if let Some(old) = running.insert(stage.to_string(), new) {
+            self.stages.finish(old);
+        }
+    }
+
+    fn on_bytes(&self, stage: &str, bytes: u64) {
+        if let Some(stage) = self.stages.running.lock().unwrap().get_mut(stage) {
+            stage.inc(bytes);
+        }
+    }
+
+    fn on_items(&self, stage: &str, items: u64) {
+        if let Some(stage) = self.stages.running.lock().unwrap().get_mut(stage) {
+            stage.items += items;
+            stage.bar.set_message(format!("{} rows", stage.items));
+            self.stages.sample_memory(stage);
+        }
+    }
+
+    fn on_stage_end(&self, stage: &str) {
+        let stage = self.stages.running.lock().unwrap().remove(stage);
+        if let Some(stage) = stage {
+            self.stages.finish(stage);
+        }
+    }
+}
+
+/// Progress of a single stage, drawn as a bar or logged periodically
+struct Stage {
+    bar: ProgressBar,
+    items: u64,
+    peak_memory: Option<u64>,
+    log_interval: Option<Duration>,
+    last_log: Instant,
+}
+
+impl Stage {
+    fn inc(&mut self, delta: u64) {
+        self.bar.inc(delta);
+
+        let Some(log_interval) = self.log_interval else {
+            return;
+        };
+        if self.last_log.elapsed() >= log_interval {
+            self.last_log = Instant::now();
+            self.log();
+        }
+    }
+
+    fn log(&self) {
+        log::info!(
+            "{}: {}/{}, {} rows, {}/s, ETA {}",
+            self.bar.prefix(),
+            HumanBytes(self.bar.position()),
+            HumanBytes(self.bar.length().unwrap_or_default()),
+            self.items,
+            HumanBytes(self.bar.per_sec() as u64),
+            HumanDuration(self.bar.eta())
+        );
+    }
+
+    fn finish(self) -> StageSummary {
+        // Bytes read so far, finishing moves the bar to its end
+        let bytes = self.bar.position();
+        self.bar.finish();
+        StageSummary {
+            name: self.bar.prefix(),
+            bytes,
+            rows: self.items,
+            elapsed: self.bar.elapsed(),
+            peak_memory: self.peak_memory,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode() {
+        assert_eq!("log".parse::<ProgressMode>().unwrap(), ProgressMode::Log);
+        assert!("bars".parse::<ProgressMode>().is_err());
+        assert_eq!(ProgressMode::Never.resolve(), ProgressMode::Never);
+        assert_ne!(ProgressMode::Auto.resolve(), ProgressMode::Auto);
+    }
+
+    #[test]
+    fn test_sink() {
+        let progress = Progress::new(ProgressMode::Never);
+        progress.on_stage("stops", 100);
+        progress.on_bytes("stops", 40);
+        progress.on_items("stops", 3);
+        progress.on_bytes("unknown", 40);
+
+        let bar = progress.stages.running.lock().unwrap()["stops"].bar.clone();
+        assert_eq!(bar.position(), 40);
+        assert_eq!(bar.message(), "3 rows");
+
+        progress.on_stage_end("stops");
+        assert!(bar.is_finished());
+        assert!(progress.stages.running.lock().unwrap().is_empty());
+
+        let finished = progress.stages.finished.lock().unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!((finished[0].bytes, finished[0].rows), (40, 3));
+        #[cfg(target_os = "linux")]
+        assert!(finished[0].peak_memory.unwrap() > 0);
+    }
+}
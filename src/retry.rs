@@ -0,0 +1,37 @@
+/// Retry and rate limiting policy shared by the network clients
+///
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Retries of requests failing with transient errors and request rate limiting
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Minimum time between two requests, zero disables rate limiting
+    pub min_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(60),
+            min_interval: Duration::ZERO,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before a retry, doubled with every attempt and jittered by up to 50%
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
@@ -0,0 +1,184 @@
+/// Canonical station records shared by gtfs feeds, masterdata and xbus
+///
+use crate::geo::Point;
+use crate::gtfs::stop_matching::ExternalStation;
+use crate::gtfs::Stop;
+use crate::hashing::FastHashMap;
+#[cfg(feature = "masterdata")]
+use crate::masterdata::Masterdata;
+#[cfg(feature = "es")]
+use crate::xbus::StationTimezoneGetter;
+
+#[derive(Debug, Clone)]
+pub struct StationRecord {
+    /// Gtfs stop_id or masterdata code
+    pub id: String,
+    /// Code shown to passengers or used by other systems, xbus trips refer to stations by it
+    pub code: Option<String>,
+    pub name: Option<String>,
+    pub timezone: Option<chrono_tz::Tz>,
+    pub location: Option<Point>,
+}
+
+#[derive(Default)]
+pub struct StationRegistry {
+    records: Vec<StationRecord>,
+    by_id: FastHashMap<String, usize>,
+    by_code: FastHashMap<String, usize>,
+}
+
+impl StationRegistry {
+    /// Add a station, replacing a previous record with the same id
+    pub fn insert(&mut self, record: StationRecord) {
+        let index = match self.by_id.get(&record.id) {
+            Some(&index) => {
+                if let Some(code) = &self.records[index].code {
+                    self.by_code.remove(code);
+                }
+                self.records[index] = record;
+                index
+            }
+            None => {
+                self.by_id.insert(record.id.clone(), self.records.len());
+                self.records.push(record);
+                self.records.len() - 1
+            }
+        };
+
+        if let Some(code) = &self.records[index].code {
+            self.by_code.insert(code.clone(), index);
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&StationRecord> {
+        self.by_id.get(id).map(|&x| &self.records[x])
+    }
+
+    pub fn get_by_code(&self, code: &str) -> Option<&StationRecord> {
+        self.by_code.get(code).map(|&x| &self.records[x])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StationRecord> {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Stops of a feed, stops without a stop_timezone get the agency timezone
+    pub fn from_gtfs_stops(stops: &[Stop], agency_timezone: Option<chrono_tz::Tz>) -> Self {
+        let mut registry = StationRegistry::default();
+
+        for stop in stops {
+            let timezone = stop
+                .stop_timezone
+                .as_ref()
+                .and_then(|x| x.parse().ok())
+                .or(agency_timezone);
+
+            registry.insert(StationRecord {
+                id: stop.stop_id.clone(),
+                code: stop.stop_code.clone(),
+                name: stop.stop_name.clone(),
+                timezone,
+                location: stop
+                    .stop_lat
+                    .zip(stop.stop_lon)
+                    .map(|(lat, lon)| Point::new(lat, lon)),
+            });
+        }
+
+        registry
+    }
+
+    /// Stations of masterdata identified by their code
+    #[cfg(feature = "masterdata")]
+    pub fn from_masterdata(masterdata: &Masterdata) -> Self {
+        let mut registry = StationRegistry::default();
+
+        for (code, timezone) in masterdata.station_timezones() {
+            registry.insert(StationRecord {
+                id: code.to_string(),
+                code: Some(code.to_string()),
+                name: None,
+                timezone: Some(*timezone),
+                location: None,
+            });
+        }
+        for station in masterdata.stations() {
+            let record = StationRecord {
+                id: station.id.clone(),
+                code: Some(station.id.clone()),
+                name: Some(station.name.clone()),
+                timezone: masterdata.get_station_timezone(&station.id).copied(),
+                location: Some(Point::new(station.lat, station.lon)),
+            };
+            registry.insert(record);
+        }
+
+        registry
+    }
+
+    /// Named stations with a location to match gtfs stops against, identified by code
+    pub fn external_stations(&self) -> Vec<ExternalStation> {
+        self.records
+            .iter()
+            .filter_map(|x| {
+                let location = x.location?;
+                Some(ExternalStation {
+                    id: x.code.clone().unwrap_or_else(|| x.id.clone()),
+                    name: x.name.clone()?,
+                    lat: location.lat,
+                    lon: location.lon,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "es")]
+impl StationTimezoneGetter for StationRegistry {
+    fn get_station_timezone(&self, station_code: &str) -> Option<&chrono_tz::Tz> {
+        self.get_by_code(station_code)?.timezone.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs::synthetic::{SyntheticFeed, SyntheticFeedParams};
+
+    #[test]
+    fn test_from_gtfs_stops() {
+        let mut feed = SyntheticFeed::generate(&SyntheticFeedParams {
+            routes: 1,
+            ..Default::default()
+        });
+        feed.stops[0].stop_code = Some("DEBERZOB".to_string());
+        feed.stops[0].stop_timezone = Some("Europe/London".to_string());
+
+        let mut registry =
+            StationRegistry::from_gtfs_stops(&feed.stops, Some(chrono_tz::Europe::Berlin));
+        assert_eq!(registry.len(), feed.stops.len());
+        assert_eq!(
+            registry.get_by_code("DEBERZOB").unwrap().timezone,
+            Some(chrono_tz::Europe::London)
+        );
+        assert_eq!(
+            registry.get(&feed.stops[1].stop_id).unwrap().timezone,
+            Some(chrono_tz::Europe::Berlin)
+        );
+
+        // Replacing a record drops its old code
+        let mut record = registry.get(&feed.stops[0].stop_id).unwrap().clone();
+        record.code = None;
+        registry.insert(record);
+        assert_eq!(registry.len(), feed.stops.len());
+        assert!(registry.get_by_code("DEBERZOB").is_none());
+    }
+}
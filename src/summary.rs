@@ -0,0 +1,263 @@
+/// Machine readable outcome of a run, written as json for orchestration to decide on publishing
+///
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::gtfs::validation::ValidationReport;
+use crate::progress::StageSummary;
+use crate::watch::write_atomically;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Ok,
+    Failed,
+    /// Stopped by a signal, outputs hold the work completed until then
+    Cancelled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedSummary {
+    pub path: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageTiming {
+    pub name: String,
+    pub seconds: f64,
+    pub bytes: u64,
+    pub rows: u64,
+    pub peak_memory: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FindingCount {
+    pub severity: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub command: String,
+    pub status: RunStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub feed: Option<FeedSummary>,
+    /// Rows read per file
+    pub rows: BTreeMap<String, u64>,
+    /// Errors by category, such as malformed_row
+    pub errors: BTreeMap<String, usize>,
+    /// Validation findings by check
+    pub findings: BTreeMap<String, FindingCount>,
+    pub stages: Vec<StageTiming>,
+    pub outputs: Vec<String>,
+    pub seconds: f64,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl RunSummary {
+    pub fn new(command: &str) -> Self {
+        RunSummary {
+            command: command.to_string(),
+            status: RunStatus::Ok,
+            error: None,
+            feed: None,
+            rows: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            findings: BTreeMap::new(),
+            stages: Vec::new(),
+            outputs: Vec::new(),
+            seconds: 0.0,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn add_stage(&mut self, stage: &StageSummary) {
+        *self.rows.entry(stage.name.clone()).or_default() += stage.rows;
+        self.stages.push(StageTiming {
+            name: stage.name.clone(),
+            seconds: stage.elapsed.as_secs_f64(),
+            bytes: stage.bytes,
+            rows: stage.rows,
+            peak_memory: stage.peak_memory,
+        });
+    }
+
+    pub fn add_errors(&mut self, category: &str, count: usize) {
+        *self.errors.entry(category.to_string()).or_default() += count;
+    }
+
+    /// Findings of a report, replacing those of a previous report
+    pub fn set_report(&mut self, report: &ValidationReport) {
+        self.findings = report
+            .findings
+            .iter()
+            .map(|x| {
+                let count = FindingCount {
+                    severity: x.severity.to_string(),
+                    count: x.count,
+                };
+                (x.check.to_string(), count)
+            })
+            .collect();
+    }
+
+    pub fn finish(&mut self, status: RunStatus, error: Option<String>) {
+        self.status = status;
+        self.error = error;
+        self.seconds = self.started.elapsed().as_secs_f64();
+    }
+}
+
+/// Summary being collected and the file it is written to
+static RUN: Mutex<Option<(PathBuf, RunSummary)>> = Mutex::new(None);
+
+/// Collect a summary of the run of a command, written to path by finish
+pub fn start<P: AsRef<Path>>(path: P, command: &str) {
+    *RUN.lock().unwrap() = Some((path.as_ref().to_path_buf(), RunSummary::new(command)));
+}
+
+/// Update the summary if one is being collected
+fn update<F: FnOnce(&mut RunSummary)>(f: F) {
+    if let Some((_, summary)) = RUN.lock().unwrap().as_mut() {
+        f(summary);
+    }
+}
+
+fn is_collecting() -> bool {
+    RUN.lock().unwrap().is_some()
+}
+
+pub fn record_stage(stage: &StageSummary) {
+    update(|x| x.add_stage(stage));
+}
+
+pub fn record_errors(category: &str, count: usize) {
+    update(|x| x.add_errors(category, count));
+}
+
+pub fn record_report(report: &ValidationReport) {
+    update(|x| x.set_report(report));
+}
+
+pub fn record_output<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref().to_string_lossy().to_string();
+    update(|x| {
+        if !x.outputs.contains(&path) {
+            x.outputs.push(path)
+        }
+    });
+}
+
+/// Record the input feed, hashing it only if a summary is being collected
+pub fn record_feed(path: &str) -> Result<()> {
+    if !is_collecting() {
+        return Ok(());
+    }
+    let sha256 = sha256_file(Path::new(path)).with_context(|| format!("Could not hash {path}"))?;
+    update(|x| {
+        x.feed = Some(FeedSummary {
+            path: path.to_string(),
+            sha256,
+        })
+    });
+    Ok(())
+}
+
+/// Write the summary if one is being collected, later calls do nothing
+pub fn finish(status: RunStatus, error: Option<String>) -> Result<()> {
+    let Some((path, mut summary)) = RUN.lock().unwrap().take() else {
+        return Ok(());
+    };
+    summary.finish(status, error);
+    let text = serde_json::to_string_pretty(&summary)?;
+    write_atomically(&path, &text)?;
+    log::info!("Wrote run summary to {path:?}");
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::gtfs::validation::rules::Severity;
+    use crate::gtfs::validation::Finding;
+
+    #[test]
+    fn test_summary_json() {
+        let mut summary = RunSummary::new("validate");
+        for rows in [0, 3] {
+            summary.add_stage(&StageSummary {
+                name: "calendar".to_string(),
+                bytes: 10,
+                rows,
+                elapsed: Duration::from_millis(500),
+                peak_memory: None,
+            });
+        }
+        summary.add_errors("malformed_row", 2);
+        summary.add_errors("malformed_row", 1);
+        summary.set_report(&ValidationReport {
+            findings: vec![Finding {
+                check: "malformed_row",
+                severity: Severity::Warning,
+                count: 3,
+                samples: Vec::new(),
+            }],
+        });
+        summary.finish(RunStatus::Failed, Some("Could not read feed".to_string()));
+
+        let value = serde_json::to_value(&summary).unwrap();
+        assert_eq!(value["status"], json!("failed"));
+        assert_eq!(value["rows"], json!({"calendar": 3}));
+        assert_eq!(value["errors"], json!({"malformed_row": 3}));
+        assert_eq!(value["findings"]["malformed_row"]["count"], json!(3));
+        assert_eq!(value["stages"][1]["seconds"], json!(0.5));
+        assert!(value.get("started").is_none());
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = std::env::temp_dir().join(format!("rdtfs-summary-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("feed.zip");
+        std::fs::write(&path, "abc").unwrap();
+
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
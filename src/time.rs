@@ -0,0 +1,205 @@
+/// Conversions between epoch millis, naive wall clock times and timezone aware datetimes
+///
+use std::str::FromStr;
+
+use anyhow::bail;
+use chrono::{DateTime, Duration, LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TimeError {
+    #[error("Timestamp {0}ms is out of range")]
+    OutOfRange(u64),
+    #[error("Local time {0} is ambiguous in {1}")]
+    Ambiguous(NaiveDateTime, String),
+    #[error("Local time {0} does not exist in {1}")]
+    Nonexistent(NaiveDateTime, String),
+}
+
+/// Choice for a wall clock time occurring twice when clocks are turned back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ambiguous {
+    Earliest,
+    Latest,
+    #[default]
+    Reject,
+}
+
+/// Choice for a wall clock time skipped when clocks are turned forward
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Nonexistent {
+    /// Read with the offset before the transition, 02:30 becomes 03:30 on a one hour jump
+    ShiftForward,
+    #[default]
+    Reject,
+}
+
+impl FromStr for Ambiguous {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "earliest" => Ambiguous::Earliest,
+            "latest" => Ambiguous::Latest,
+            "reject" => Ambiguous::Reject,
+            _ => {
+                bail!("Unknown policy {s} for ambiguous times, expected earliest, latest or reject")
+            }
+        })
+    }
+}
+
+impl FromStr for Nonexistent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "shift" => Nonexistent::ShiftForward,
+            "reject" => Nonexistent::Reject,
+            _ => bail!("Unknown policy {s} for skipped times, expected shift or reject"),
+        })
+    }
+}
+
+/// Resolution of local times that do not map to exactly one instant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LocalTimePolicy {
+    pub ambiguous: Ambiguous,
+    pub nonexistent: Nonexistent,
+}
+
+/// Milliseconds since the unix epoch as a naive UTC datetime
+pub fn millis_to_naive(millis: u64) -> Result<NaiveDateTime, TimeError> {
+    let secs = i64::try_from(millis / 1000).map_err(|_| TimeError::OutOfRange(millis))?;
+    let nsecs = (millis % 1000) as u32 * 1_000_000;
+    NaiveDateTime::from_timestamp_opt(secs, nsecs).ok_or(TimeError::OutOfRange(millis))
+}
+
+/// Milliseconds since the unix epoch of a naive UTC datetime
+pub fn naive_to_millis(datetime: &NaiveDateTime) -> i64 {
+    datetime.timestamp_millis()
+}
+
+/// Instant shown by the clocks of tz at a wall clock time
+pub fn resolve_local(
+    tz: &Tz,
+    local: &NaiveDateTime,
+    policy: LocalTimePolicy,
+) -> Result<DateTime<Tz>, TimeError> {
+    match tz.from_local_datetime(local) {
+        LocalResult::Single(value) => Ok(value),
+        LocalResult::Ambiguous(earliest, latest) => match policy.ambiguous {
+            Ambiguous::Earliest => Ok(earliest),
+            Ambiguous::Latest => Ok(latest),
+            Ambiguous::Reject => Err(TimeError::Ambiguous(*local, tz.name().to_string())),
+        },
+        LocalResult::None => match policy.nonexistent {
+            Nonexistent::ShiftForward => Ok(shift_forward(tz, local)),
+            Nonexistent::Reject => Err(TimeError::Nonexistent(*local, tz.name().to_string())),
+        },
+    }
+}
+
+/// Wall clock time in tz_to at the moment clocks in tz_from show local
+pub fn convert_naive(
+    local: &NaiveDateTime,
+    tz_from: &Tz,
+    tz_to: &Tz,
+    policy: LocalTimePolicy,
+) -> Result<NaiveDateTime, TimeError> {
+    Ok(resolve_local(tz_from, local, policy)?
+        .with_timezone(tz_to)
+        .naive_local())
+}
+
+/// Local time in a gap read with the offset in effect before the gap
+fn shift_forward(tz: &Tz, local: &NaiveDateTime) -> DateTime<Tz> {
+    // Transitions are far less than a day apart from each other
+    let before = tz.offset_from_utc_datetime(&(*local - Duration::days(1)));
+    let utc = *local - Duration::seconds(before.fix().local_minus_utc().into());
+    tz.from_utc_datetime(&utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn datetime(day: u32, month: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_millis() {
+        let value = datetime(1, 5, 8, 0) + Duration::milliseconds(250);
+        assert_eq!(naive_to_millis(&value), 1_682_928_000_250);
+        assert_eq!(millis_to_naive(1_682_928_000_250).unwrap(), value);
+        assert_eq!(
+            millis_to_naive(u64::MAX),
+            Err(TimeError::OutOfRange(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        // Clocks in Berlin went from 03:00 back to 02:00 on 2023-10-29
+        let local = datetime(29, 10, 2, 30);
+        let policy = |ambiguous| LocalTimePolicy {
+            ambiguous,
+            ..Default::default()
+        };
+
+        let earliest = resolve_local(&Berlin, &local, policy(Ambiguous::Earliest)).unwrap();
+        let latest = resolve_local(&Berlin, &local, policy(Ambiguous::Latest)).unwrap();
+        assert_eq!(earliest.naive_utc(), datetime(29, 10, 0, 30));
+        assert_eq!(latest.naive_utc(), datetime(29, 10, 1, 30));
+        assert!(matches!(
+            resolve_local(&Berlin, &local, policy(Ambiguous::Reject)),
+            Err(TimeError::Ambiguous(..))
+        ));
+    }
+
+    #[test]
+    fn test_nonexistent() {
+        // Clocks in Berlin went from 02:00 to 03:00 on 2023-03-26
+        let local = datetime(26, 3, 2, 30);
+        let policy = LocalTimePolicy {
+            nonexistent: Nonexistent::ShiftForward,
+            ..Default::default()
+        };
+
+        let shifted = resolve_local(&Berlin, &local, policy).unwrap();
+        assert_eq!(shifted.naive_local(), datetime(26, 3, 3, 30));
+        assert!(matches!(
+            resolve_local(&Berlin, &local, LocalTimePolicy::default()),
+            Err(TimeError::Nonexistent(..))
+        ));
+    }
+
+    #[test]
+    fn test_policy_names() {
+        assert_eq!("latest".parse::<Ambiguous>().unwrap(), Ambiguous::Latest);
+        assert_eq!(
+            "shift".parse::<Nonexistent>().unwrap(),
+            Nonexistent::ShiftForward
+        );
+        assert!("first".parse::<Ambiguous>().is_err());
+    }
+
+    #[test]
+    fn test_convert_naive() {
+        let berlin = convert_naive(
+            &datetime(1, 5, 8, 0),
+            &chrono_tz::UTC,
+            &Berlin,
+            LocalTimePolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(berlin, datetime(1, 5, 10, 0));
+    }
+}
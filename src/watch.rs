@@ -0,0 +1,137 @@
+/// Polling a feed file for new versions and publishing outputs atomically
+///
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File},
+    hash::Hasher,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+
+/// Modification time and size, cheap to check on every poll
+type FileStamp = (SystemTime, u64);
+
+pub struct FeedWatcher {
+    path: PathBuf,
+    /// Stamp seen on the previous poll, waiting to settle
+    pending: Option<FileStamp>,
+    processed: Option<FileStamp>,
+    content_hash: Option<u64>,
+}
+
+impl FeedWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        FeedWatcher {
+            path: path.as_ref().to_path_buf(),
+            pending: None,
+            processed: None,
+            content_hash: None,
+        }
+    }
+
+    /// Check the file for a new version, true if its content changed since the last version
+    ///
+    /// A changed file is reported only once its stamp stays the same for two polls,
+    /// so a feed that is still being copied is not picked up half written.
+    pub fn poll(&mut self) -> Result<bool> {
+        let metadata =
+            fs::metadata(&self.path).with_context(|| format!("Could not stat {:?}", self.path))?;
+        let stamp = (metadata.modified()?, metadata.len());
+
+        if self.processed == Some(stamp) {
+            return Ok(false);
+        }
+
+        if self.processed.is_some() && self.pending != Some(stamp) {
+            self.pending = Some(stamp);
+            return Ok(false);
+        }
+
+        self.pending = None;
+        self.processed = Some(stamp);
+
+        // Touched or re-uploaded without changes
+        let content_hash = hash_file(&self.path)?;
+        if self.content_hash == Some(content_hash) {
+            return Ok(false);
+        }
+        self.content_hash = Some(content_hash);
+
+        Ok(true)
+    }
+}
+
+fn hash_file(path: &Path) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = DefaultHasher::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buf[..read]);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Replace file contents so that readers see either the old or the new version
+pub fn write_atomically<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp_name = path
+        .file_name()
+        .context("Output path has no file name")?
+        .to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file =
+        File::create(&tmp_path).with_context(|| format!("Could not create {tmp_path:?}"))?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&tmp_path, path).with_context(|| format!("Could not replace {path:?}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll() {
+        let dir = std::env::temp_dir().join(format!("rdtfs-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let feed = dir.join("feed.zip");
+
+        write_atomically(&feed, "first").unwrap();
+        let mut watcher = FeedWatcher::new(&feed);
+        assert!(watcher.poll().unwrap());
+        assert!(!watcher.poll().unwrap());
+
+        // Longer content changes the stamp even on coarse mtime resolution
+        write_atomically(&feed, "second version").unwrap();
+        assert!(!watcher.poll().unwrap());
+        assert!(watcher.poll().unwrap());
+
+        // Same content written again through a version of the same length, the
+        // mtime is moved forward as coarse resolution could keep the stamp unchanged
+        write_atomically(&feed, "SECOND VERSION").unwrap();
+        write_atomically(&feed, "second version").unwrap();
+        let (modified, _) = watcher.processed.unwrap();
+        let file = File::options().write(true).open(&feed).unwrap();
+        file.set_modified(modified + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(!watcher.poll().unwrap());
+        assert!(!watcher.poll().unwrap());
+        // Settled and compared by content
+        assert_ne!(watcher.processed.unwrap().0, modified);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,33 @@
 /// Sending requests and parsing responses of elasticsearch
 ///
 ///
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::TimeZone;
 use elasticsearch::auth::Credentials;
+use elasticsearch::http::response::Response;
 use elasticsearch::http::transport::{SingleNodeConnectionPool, TransportBuilder};
-use elasticsearch::{Elasticsearch, SearchParts};
+use elasticsearch::http::StatusCode;
+use elasticsearch::{Elasticsearch, OpenPointInTimeParts, SearchParts};
 
+use futures_util::{pin_mut, stream, Stream, StreamExt, TryStreamExt};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tracing::Instrument;
+
+use crate::cancel;
+use crate::retry::RetryPolicy;
+use crate::time::{self, LocalTimePolicy};
+
+pub mod ndjson;
+pub mod query;
+
+use query::{SortOrder, TripsQuery};
 
 fn nullstring() -> Option<String> {
     None
@@ -50,10 +66,48 @@ pub struct Vehicle {
     pub vehicle_type: VehicleType,
 }
 
-#[derive(Debug, Clone)]
+/// Datetime with offset and timezone name, "2023-05-01T10:00:00+02:00[Europe/Berlin]"
+///
+/// Plain rfc3339 keeps only the offset, the station timezone is lost on replay.
+mod tz_datetime {
+    use chrono::{DateTime, TimeZone};
+    use chrono_tz::Tz;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &DateTime<Tz>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!(
+            "{}[{}]",
+            value.to_rfc3339(),
+            value.timezone().name()
+        ))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Tz>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+
+        let Some((datetime, tz)) = value.strip_suffix(']').and_then(|x| x.split_once('[')) else {
+            return Err(de::Error::custom(format!(
+                "Expected datetime[timezone], got {value}"
+            )));
+        };
+        let tz: Tz = tz.parse().map_err(de::Error::custom)?;
+        let datetime = DateTime::parse_from_rfc3339(datetime).map_err(de::Error::custom)?;
+
+        Ok(tz.from_utc_datetime(&datetime.naive_utc()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Segment {
     pub line: Option<String>,
+    #[serde(with = "tz_datetime")]
     pub departure_time: chrono::DateTime<chrono_tz::Tz>,
+    #[serde(with = "tz_datetime")]
     pub arrival_time: chrono::DateTime<chrono_tz::Tz>,
     pub departure_station: Uid,
     pub arrival_station: Uid,
@@ -83,19 +137,21 @@ struct FareRaw {
     pub fare_class: Uid,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Fare {
     pub price: rust_decimal::Decimal,
     pub fare_class: Uid,
     pub currency: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TripsHit {
     pub snapshot_id: String,
     pub snapshot_timestamp: chrono::DateTime<chrono::Utc>,
     pub snapshot_uid: String,
+    #[serde(with = "tz_datetime")]
     pub departure_time: chrono::DateTime<chrono_tz::Tz>,
+    #[serde(with = "tz_datetime")]
     pub arrival_time: chrono::DateTime<chrono_tz::Tz>,
     pub total_price: rust_decimal::Decimal,
     pub currency: String,
@@ -144,10 +200,59 @@ struct TripsHitRaw {
     pub fares: Option<Vec<FareRaw>>,
 }
 
+/// Statuses returned by an overloaded or restarting cluster
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Delay requested by the server in seconds form of the Retry-After header
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get("retry-after")?.to_str().ok()?;
+    Some(Duration::from_secs(value.trim().parse().ok()?))
+}
+
+/// Shape of trips search responses
+///
+/// Hits are parsed from _source, so excluded fields must be optional in `TripsHitRaw`.
+#[derive(Debug, Clone)]
+pub struct EsTripsOptions {
+    /// Hits per page, elasticsearch caps this at 10k by default
+    pub page_size: i64,
+    /// Only return these _source fields, all if empty
+    pub source_includes: Vec<String>,
+    pub source_excludes: Vec<String>,
+    /// Keep hits of the remaining indices when shards of some indices fail
+    pub allow_partial_results: bool,
+    /// Resolution of departure and arrival times around daylight saving transitions
+    pub local_time_policy: LocalTimePolicy,
+}
+
+impl Default for EsTripsOptions {
+    fn default() -> Self {
+        EsTripsOptions {
+            page_size: 100,
+            source_includes: Vec::new(),
+            source_excludes: Vec::new(),
+            allow_partial_results: false,
+            local_time_policy: LocalTimePolicy::default(),
+        }
+    }
+}
+
 pub struct EsTrips<G> {
     elastic: Elasticsearch,
-    index: String,
+    /// Index names or patterns such as trips-2023-*
+    indices: Vec<String>,
     tz_getter: G,
+    retry: RetryPolicy,
+    options: EsTripsOptions,
+    last_request: Mutex<Option<Instant>>,
 }
 
 fn make_es_client(url: &str, id: &str, api_key: &str) -> anyhow::Result<Elasticsearch> {
@@ -181,6 +286,8 @@ pub struct IndexInfo {
 struct ElasticsearchHit {
     #[serde(rename = "_source")]
     pub source: TripsHitRaw,
+    #[serde(default)]
+    pub sort: Vec<serde_json::Value>,
 }
 
 #[derive(Deserialize)]
@@ -194,12 +301,76 @@ struct ElasticsearchHits {
     pub hits: Vec<ElasticsearchHit>,
 }
 
+#[derive(Deserialize)]
+struct ShardFailure {
+    index: Option<String>,
+    reason: serde_json::Value,
+}
+
+#[derive(Deserialize, Default)]
+struct ShardsInfo {
+    failed: u64,
+    #[serde(default)]
+    failures: Vec<ShardFailure>,
+}
+
 #[derive(Deserialize)]
 struct ElasticsearchResponse {
     hits: ElasticsearchHits,
+    pit_id: Option<String>,
+    #[serde(rename = "_shards", default)]
+    shards: ShardsInfo,
 }
 
 #[derive(Deserialize)]
+struct PointInTimeResponse {
+    id: String,
+}
+
+/// Optional restrictions of a trips query on top of the carrier
+#[derive(Debug, Clone, Default)]
+pub struct TripsFilter {
+    /// First departure date to include
+    pub departure_date_from: Option<chrono::NaiveDate>,
+    /// Last departure date to include
+    pub departure_date_to: Option<chrono::NaiveDate>,
+    /// Keep trips departing from any of these stations, all if empty
+    pub departure_stations: Vec<String>,
+    /// Keep trips arriving at any of these stations, all if empty
+    pub arrival_stations: Vec<String>,
+    pub booked_out: Option<bool>,
+}
+
+/// Carrier with the number of trips indexed for it
+#[derive(Debug, Clone)]
+pub struct CarrierCount {
+    pub carrier: String,
+    pub trips: u64,
+}
+
+/// Position of a paginated search
+enum PagingState {
+    Start,
+    Paging {
+        pit_id: String,
+        after: serde_json::Value,
+    },
+    Done,
+}
+
+/// How long elasticsearch keeps the point in time between two pages
+const PIT_KEEP_ALIVE: &str = "5m";
+
+/// One page of trips of a point in time search
+pub struct TripsPage {
+    pub hits: Vec<TripsHit>,
+    /// Sort values of the last hit, passed as search_after to get the next page
+    pub search_after: Option<serde_json::Value>,
+    /// Point in time id to use for the next page, elasticsearch may change it between pages
+    pub pit_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
 struct AggKey {
     value: String,
 }
@@ -207,12 +378,12 @@ struct AggKey {
 #[derive(Deserialize)]
 struct AggBucket {
     key: AggKey,
-    // doc_count: u32,
+    doc_count: u64,
 }
 
 #[derive(Deserialize)]
 struct AggResult3 {
-    // after_key: AggKey,
+    after_key: Option<AggKey>,
     buckets: Vec<AggBucket>,
 }
 #[derive(Deserialize)]
@@ -225,6 +396,8 @@ struct AggResponse {
     // took: u32,
     // timed_out: bool,
     aggregations: AggResult,
+    #[serde(rename = "_shards", default)]
+    shards: ShardsInfo,
 }
 
 fn convert_line_id(suffix: Option<String>, prefix: Option<String>) -> Option<String> {
@@ -249,67 +422,30 @@ fn convert_line_id(suffix: Option<String>, prefix: Option<String>) -> Option<Str
     }
 }
 
-fn millis_to_naive_datetime(millis: u64) -> anyhow::Result<chrono::naive::NaiveDateTime> {
-    let result = {
-        let secs = millis / 1000;
-        let nsecs = (millis - secs * 1000) * 1000000;
-
-        match chrono::naive::NaiveDateTime::from_timestamp_opt(secs.try_into()?, nsecs.try_into()?)
-        {
-            Some(val) => anyhow::Ok(val),
-            None => bail!("Time is out of range"),
-        }
-    }
-    .context("Could not convert millis to datetime")?;
-    Ok(result)
-}
-
-fn convert_tz_naive(
-    dt: &chrono::naive::NaiveDateTime,
-    tz_from: &chrono_tz::Tz,
-    tz_to: &chrono_tz::Tz,
-) -> anyhow::Result<chrono::naive::NaiveDateTime> {
-    let local_dttm = match tz_from.from_local_datetime(dt) {
-        chrono::LocalResult::None => bail!("Could not convert timezones"),
-        chrono::LocalResult::Single(val) => val,
-        chrono::LocalResult::Ambiguous(_, _) => {
-            bail!("Ambiguous timezone conversion to Berlin time")
-        }
-    };
-
-    Ok(local_dttm.with_timezone(tz_to).naive_local())
-}
-
-// use chrono_tz::OffsetName::
-fn elastic_naive_to_tz_aware(
-    dt: &chrono::naive::NaiveDateTime,
-    station_timezone: &chrono_tz::Tz,
-) -> anyhow::Result<chrono::DateTime<chrono_tz::Tz>> {
-    let step1 = convert_tz_naive(dt, &chrono_tz::UTC, &chrono_tz::Europe::Berlin)?;
-    let step2 = match station_timezone.from_local_datetime(&step1) {
-        chrono::LocalResult::None => bail!("Could not convert to station timezone"),
-        chrono::LocalResult::Single(val) => val,
-        chrono::LocalResult::Ambiguous(_, _) => bail!("Ambiguous local timezone"),
-    };
-    Ok(step2)
-}
+/// Timezone of the wall clock times elasticsearch stores as if they were UTC
+const ELASTIC_TIMEZONE: chrono_tz::Tz = chrono_tz::Europe::Berlin;
 
+/// Elasticsearch millis are Berlin wall clock times, read as wall clock times of the station
 fn elastic_timestamp_to_datetime(
     ts: u64,
     station_timezone: &chrono_tz::Tz,
+    policy: LocalTimePolicy,
 ) -> anyhow::Result<chrono::DateTime<chrono_tz::Tz>> {
-    elastic_naive_to_tz_aware(&millis_to_naive_datetime(ts)?, station_timezone)
+    let naive = time::millis_to_naive(ts)?;
+    let wall_clock = time::convert_naive(&naive, &chrono_tz::UTC, &ELASTIC_TIMEZONE, policy)?;
+    Ok(time::resolve_local(station_timezone, &wall_clock, policy)?)
 }
 
 fn process_segment(
     segment: SegmentRaw,
     departure_station_tz: &chrono_tz::Tz,
     arrival_station_tz: &chrono_tz::Tz,
+    policy: LocalTimePolicy,
 ) -> anyhow::Result<Segment> {
     let departure_dttm_naive =
-        elastic_timestamp_to_datetime(segment.departure_time, departure_station_tz)?;
+        elastic_timestamp_to_datetime(segment.departure_time, departure_station_tz, policy)?;
     let arrival_dttm_naive =
-        elastic_timestamp_to_datetime(segment.arrival_time, arrival_station_tz)?;
+        elastic_timestamp_to_datetime(segment.arrival_time, arrival_station_tz, policy)?;
 
     Ok(Segment {
         line: convert_line_id(segment.line, segment.line_prefix),
@@ -330,7 +466,18 @@ fn xbus_to_money(price: u32) -> rust_decimal::Decimal {
     rust_decimal::Decimal::new(price_converted, 0) / rust_decimal::Decimal::new(100, 0)
 }
 
-fn parse_trip_hit<G>(hit: TripsHitRaw, tz_getter: &G) -> anyhow::Result<TripsHit>
+fn station_timezone<G: StationTimezoneGetter>(tz_getter: &G, uid: &str) -> Result<chrono_tz::Tz> {
+    tz_getter
+        .get_station_timezone(uid)
+        .copied()
+        .with_context(|| format!("Station {uid} unknown, masterdata has no timezone for it"))
+}
+
+fn parse_trip_hit<G>(
+    hit: TripsHitRaw,
+    tz_getter: &G,
+    policy: LocalTimePolicy,
+) -> anyhow::Result<TripsHit>
 where
     G: StationTimezoneGetter,
 {
@@ -347,15 +494,11 @@ where
     fares.reserve(hit_fares.len());
 
     for segment in hit.segments {
-        let departure_station_tz = tz_getter
-            .get_station_timezone(&segment.departure_station.uid)
-            .context("Could not get departure station timezone")?;
-        let arrival_station_tz = tz_getter
-            .get_station_timezone(&segment.arrival_station.uid)
-            .context("Could not get arrival station timezone")?;
+        let departure_station_tz = station_timezone(tz_getter, &segment.departure_station.uid)?;
+        let arrival_station_tz = station_timezone(tz_getter, &segment.arrival_station.uid)?;
 
         segments.push(
-            process_segment(segment, &departure_station_tz, &arrival_station_tz)
+            process_segment(segment, &departure_station_tz, &arrival_station_tz, policy)
                 .context("Could not understand segment data")?,
         )
     }
@@ -368,28 +511,21 @@ where
         })
     }
 
-    let timestamp = millis_to_naive_datetime(hit.snapshot_timestamp)
+    let timestamp = time::millis_to_naive(hit.snapshot_timestamp)
         .context("Snapshot timestamp not understood")?;
-    let tz_aware_datetime = match chrono::Utc.from_local_datetime(&timestamp) {
-        chrono::LocalResult::Single(value) => value,
-        _ => bail!("Could not convert timestamp to UTC"),
-    };
+    let tz_aware_datetime = chrono::Utc.from_utc_datetime(&timestamp);
 
-    let dep_tz = tz_getter
-        .get_station_timezone(&hit.departure_station.uid)
-        .context("Could not get departure station timezone")?;
+    let dep_tz = station_timezone(tz_getter, &hit.departure_station.uid)?;
 
-    let arr_tz = tz_getter
-        .get_station_timezone(&hit.departure_station.uid)
-        .context("Could not get departure station timezone")?;
+    let arr_tz = station_timezone(tz_getter, &hit.departure_station.uid)?;
 
     Ok(TripsHit {
         snapshot_id: hit.snapshot_id,
         snapshot_timestamp: tz_aware_datetime,
         snapshot_uid: hit.snapshot_uid,
-        departure_time: elastic_timestamp_to_datetime(hit.departure_time, &dep_tz)
+        departure_time: elastic_timestamp_to_datetime(hit.departure_time, &dep_tz, policy)
             .context("Could not parse trip hit departure time")?,
-        arrival_time: elastic_timestamp_to_datetime(hit.arrival_time, &arr_tz)
+        arrival_time: elastic_timestamp_to_datetime(hit.arrival_time, &arr_tz, policy)
             .context("Could not parse trip arrival time")?,
         total_price: xbus_to_money(hit.total_price),
         currency: hit.currency,
@@ -412,9 +548,10 @@ impl<G> EsTrips<G>
 where
     G: StationTimezoneGetter,
 {
+    /// Search trips in all given indices, names may be patterns such as trips-*
     pub fn new(
         url: &str,
-        index: &str,
+        indices: &[String],
         api_id: &str,
         api_key: &str,
         tz_getter: G,
@@ -424,75 +561,262 @@ where
 
         Ok(EsTrips {
             elastic,
-            index: index.to_string(),
+            indices: indices.to_vec(),
             tz_getter,
+            retry: RetryPolicy::default(),
+            options: EsTripsOptions::default(),
+            last_request: Mutex::new(None),
         })
     }
 
-    pub async fn index_info(&self) -> anyhow::Result<IndexInfo> {
+    fn index_names(&self) -> Vec<&str> {
+        self.indices.iter().map(|x| x.as_str()).collect()
+    }
+
+    /// Fail on shard failures unless partial results are allowed, then only log them
+    fn check_shards(&self, shards: &ShardsInfo) -> Result<()> {
+        if shards.failed == 0 {
+            return Ok(());
+        }
+
+        let mut indices: Vec<&str> = shards
+            .failures
+            .iter()
+            .filter_map(|x| x.index.as_deref())
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        let reason = shards
+            .failures
+            .first()
+            .map(|x| x.reason.to_string())
+            .unwrap_or_default();
+
+        if !self.options.allow_partial_results {
+            bail!(
+                "{} shards of indices [{}] failed: {}",
+                shards.failed,
+                indices.join(", "),
+                reason
+            );
+        }
+        log::warn!(
+            "{} shards of indices [{}] failed, results are partial: {}",
+            shards.failed,
+            indices.join(", "),
+            reason
+        );
+        Ok(())
+    }
+
+    pub fn with_options(mut self, options: EsTripsOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Wait until the minimum interval since the previous request has passed
+    async fn wait_for_rate_limit(&self) {
+        let wait = {
+            let mut last_request = self.last_request.lock().unwrap();
+            let now = Instant::now();
+            let next = last_request
+                .map(|x| x + self.retry.min_interval)
+                .filter(|x| *x > now);
+            *last_request = Some(next.unwrap_or(now));
+            next.map(|x| x - now)
+        };
+
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Send a request, retrying connection errors and transient statuses
+    async fn send<F, Fut>(&self, request: F) -> Result<Response>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Response, elasticsearch::Error>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            self.wait_for_rate_limit().await;
+
+            let (delay, reason) = match request().await {
+                Ok(response) if !is_transient(response.status_code()) => return Ok(response),
+                Ok(response) => (
+                    retry_after(&response).unwrap_or_else(|| self.retry.backoff(attempt)),
+                    format!("status {}", response.status_code()),
+                ),
+                Err(err) => (self.retry.backoff(attempt), err.to_string()),
+            };
+
+            if attempt >= self.retry.max_retries {
+                bail!(
+                    "Elasticsearch request failed after {} retries: {}",
+                    attempt,
+                    reason
+                );
+            }
+            attempt += 1;
+
+            log::warn!(
+                "Elasticsearch request failed with {reason}, retry {attempt} in {delay:.1?}"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Info of every index matching the configured names and patterns
+    pub async fn index_info(&self) -> anyhow::Result<Vec<IndexInfo>> {
+        let indices = self.index_names();
+        let cat = self.elastic.cat();
         let response = self
-            .elastic
-            .cat()
-            .indices(elasticsearch::cat::CatIndicesParts::Index(&[self
-                .index
-                .as_str()]))
-            .format("json")
-            .send()
+            .send(|| {
+                cat.indices(elasticsearch::cat::CatIndicesParts::Index(&indices))
+                    .format("json")
+                    .send()
+            })
             .await?;
 
         let index_info = {
             let status_code = response.status_code();
             let response_text = response.text().await?;
-            let mut response_body = serde_json::from_str::<Vec<IndexInfo>>(&response_text)
-                .with_context(|| {
-                    format!(
-                        "Index info response is not recognized. Server respondend with status code: {}; Response: {}",
-                        status_code, response_text,
-                    )
-                })?;
-            if response_body.len() > 1 {
-                Err(anyhow!("Expected array of length 1"))
-            } else {
-                Ok(response_body.pop().unwrap())
-            }
+            serde_json::from_str::<Vec<IndexInfo>>(&response_text).with_context(|| {
+                format!(
+                    "Index info response is not recognized. Server respondend with status code: {}; Response: {}",
+                    status_code, response_text,
+                )
+            })
         }
-        .with_context(|| format!("/_cat/indices/{} response not understood", self.index))?;
+        .with_context(|| {
+            format!(
+                "/_cat/indices/{} response not understood",
+                self.indices.join(",")
+            )
+        })?;
 
         Ok(index_info)
     }
 
-    /// Get trips with given key
-    pub async fn get_connections(
-        &self,
-        carrier: &str,
-        after: Option<&str>,
-    ) -> anyhow::Result<Vec<TripsHit>> {
-        let es_max: i64 = 100;
-
-        let mut query = json!({
-            "query": {
-                "bool": {
-                    "must": [
-                        {"term": {"marketing_carrier.uid": carrier}},
-                    ],
-                }
-            },
-            "sort": [
-                {"snapshot_id": "asc"},
-            ]
-        });
+    /// All marketing carriers of the indices with their number of trips
+    pub async fn list_carriers(&self) -> Result<Vec<CarrierCount>> {
+        let indices = self.index_names();
+        let mut after: Option<AggKey> = None;
+        let mut carriers = Vec::new();
 
-        // Add search after if it is present in the request
-        if let Some(after) = after {
-            query["search_after"] = json!([after]);
+        loop {
+            let mut composite = json!({
+                "size": 1000,
+                "sources": [
+                    {"value": {"terms": {"field": "marketing_carrier.uid"}}},
+                ],
+            });
+            if let Some(after) = &after {
+                composite["after"] = json!(after);
+            }
+            let query = json!({
+                "size": 0,
+                "aggs": {"values": {"composite": composite}},
+            });
+
+            let response = self
+                .send(|| {
+                    self.elastic
+                        .search(SearchParts::Index(&indices))
+                        .body(query.clone())
+                        .send()
+                })
+                .await?
+                .error_for_status_code()
+                .context("Could not aggregate carriers")?;
+            let response = response.json::<AggResponse>().await?;
+            self.check_shards(&response.shards)?;
+            let values = response.aggregations.values;
+
+            carriers.extend(values.buckets.into_iter().map(|x| CarrierCount {
+                carrier: x.key.value,
+                trips: x.doc_count,
+            }));
+
+            match values.after_key {
+                Some(after_key) => after = Some(after_key),
+                None => break,
+            }
         }
 
+        Ok(carriers)
+    }
+
+    /// Open point in time so that pages are read from a consistent view of the indices
+    ///
+    /// Missing indices of an explicit list are skipped if partial results are allowed.
+    pub async fn open_point_in_time(&self) -> Result<String> {
+        let indices = self.index_names();
         let response = self
-            .elastic
-            .search(SearchParts::Index(&[self.index.as_str()]))
-            .size(es_max) // Maximum 1k records
-            .body(query)
-            .send()
+            .send(|| {
+                self.elastic
+                    .open_point_in_time(OpenPointInTimeParts::Index(&indices))
+                    .keep_alive(PIT_KEEP_ALIVE)
+                    .ignore_unavailable(self.options.allow_partial_results)
+                    .send()
+            })
+            .await?
+            .error_for_status_code()
+            .context("Could not open point in time")?;
+
+        Ok(response.json::<PointInTimeResponse>().await?.id)
+    }
+
+    pub async fn close_point_in_time(&self, pit_id: &str) -> Result<()> {
+        self.send(|| {
+            self.elastic
+                .close_point_in_time()
+                .body(json!({ "id": pit_id }))
+                .send()
+        })
+        .await?
+        .error_for_status_code()
+        .context("Could not close point in time")?;
+        Ok(())
+    }
+
+    /// Get a page of trips of a carrier
+    ///
+    /// Hits are sorted by snapshot_id with _shard_doc as tiebreaker, so paging with
+    /// search_after neither skips nor repeats hits sharing a snapshot_id. _shard_doc is
+    /// unique across all indices of the point in time, so pages of several indices merge
+    /// into a single ordered sequence.
+    pub async fn get_connections(
+        &self,
+        carrier: &str,
+        filter: &TripsFilter,
+        pit_id: &str,
+        after: Option<&serde_json::Value>,
+    ) -> anyhow::Result<TripsPage> {
+        let query = TripsQuery::new()
+            .carrier(carrier)
+            .filter(filter)
+            .sort_by("snapshot_id", SortOrder::Asc)
+            .sort_by("_shard_doc", SortOrder::Asc)
+            .point_in_time(pit_id, PIT_KEEP_ALIVE)
+            .search_after(after)
+            .source(&self.options.source_includes, &self.options.source_excludes);
+
+        // Index is part of the point in time and must not be given with the request
+        let response = self
+            .send(|| {
+                self.elastic
+                    .search(SearchParts::None)
+                    .size(self.options.page_size)
+                    .body(query.clone())
+                    .send()
+            })
             .await?;
 
         let response_text = response
@@ -525,39 +849,133 @@ where
             }
         };
 
+        self.check_shards(&response_body.shards)?;
+
+        let search_after = response_body
+            .hits
+            .hits
+            .last()
+            .map(|x| serde_json::Value::from(x.sort.clone()));
         let mut result = Vec::new();
 
         for hit in response_body.hits.hits {
             result.push(
-                parse_trip_hit(hit.source, &self.tz_getter).context("Could not parse trip hit")?,
+                parse_trip_hit(hit.source, &self.tz_getter, self.options.local_time_policy)
+                    .context("Could not parse trip hit")?,
             );
         }
 
-        Ok(result)
+        Ok(TripsPage {
+            hits: result,
+            search_after,
+            pit_id: response_body.pit_id.unwrap_or_else(|| pit_id.to_string()),
+        })
+    }
+
+    /// Stream all connections of a carrier, pages are requested as the stream is polled
+    ///
+    /// The point in time is closed once the stream is exhausted or fails, a stream
+    /// dropped early leaves it to expire after `PIT_KEEP_ALIVE`.
+    pub fn stream_connections<'a>(
+        &'a self,
+        carrier: &'a str,
+        filter: &'a TripsFilter,
+    ) -> impl Stream<Item = Result<TripsHit>> + 'a {
+        let pages = stream::try_unfold(PagingState::Start, move |state| async move {
+            let (pit_id, after) = match state {
+                PagingState::Start => (self.open_point_in_time().await?, None),
+                PagingState::Paging { pit_id, after } => (pit_id, Some(after)),
+                PagingState::Done => return Ok(None),
+            };
+
+            let page = match self
+                .get_connections(carrier, filter, &pit_id, after.as_ref())
+                .await
+            {
+                Ok(value) => value,
+                Err(err) => {
+                    self.release_point_in_time(&pit_id).await;
+                    return Err(err);
+                }
+            };
+
+            let next = match page.search_after {
+                Some(after) => PagingState::Paging {
+                    pit_id: page.pit_id,
+                    after,
+                },
+                None => {
+                    self.release_point_in_time(&page.pit_id).await;
+                    PagingState::Done
+                }
+            };
+
+            Ok(Some((page.hits, next)))
+        });
+
+        pages
+            .map_ok(|hits| stream::iter(hits.into_iter().map(Ok)))
+            .try_flatten()
+    }
+
+    /// Close point in time, failures are only logged as it expires by itself
+    async fn release_point_in_time(&self, pit_id: &str) {
+        if let Err(err) = self.close_point_in_time(pit_id).await {
+            log::warn!("{err:#}");
+        }
     }
 
     /// Consume all connections of carrier into a function
     pub async fn consume_into<F: FnMut(TripsHit) -> ()>(
         &self,
         carrier: &str,
+        filter: &TripsFilter,
         mut target: F,
     ) -> Result<()> {
-        let mut after: Option<String> = None;
+        let hits = self.stream_connections(carrier, filter);
+        pin_mut!(hits);
+
+        let mut trips: u64 = 0;
+        while let Some(hit) = hits.try_next().await? {
+            cancel::check()?;
+            trips += 1;
+            target(hit)
+        }
 
-        loop {
-            let hits = self
-                .get_connections(carrier, after.as_ref().map(|x| x.as_str()))
-                .await?;
+        tracing::Span::current().record("trips", trips);
+        Ok(())
+    }
 
-            if let Some(last) = hits.last() {
-                after = Some(last.snapshot_id.clone())
-            } else {
-                break;
-            }
-            for hit in hits {
-                target(hit)
-            }
-        }
+    /// Consume connections of several carriers, fetching up to `concurrency` carriers at once
+    ///
+    /// Hits of a carrier arrive in snapshot order, hits of different carriers are
+    /// interleaved; the target gets the carrier to route hits into per-carrier sinks.
+    pub async fn consume_carriers_into<F: FnMut(&str, TripsHit)>(
+        &self,
+        carriers: &[String],
+        filter: &TripsFilter,
+        concurrency: usize,
+        target: F,
+    ) -> Result<()> {
+        // Carrier futures are polled by a single task, so the target is never borrowed twice
+        let target = RefCell::new(target);
+
+        stream::iter(carriers)
+            .map(|carrier| {
+                let target = &target;
+                let span = tracing::info_span!("fetch", carrier, trips = tracing::field::Empty);
+                async move {
+                    self.consume_into(carrier, filter, |hit| (target.borrow_mut())(carrier, hit))
+                        .await
+                        .with_context(|| format!("Could not fetch trips of {carrier}"))?;
+                    tracing::info!("Fetched all trips of {carrier}");
+                    Ok::<_, anyhow::Error>(())
+                }
+                .instrument(span)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .try_collect::<Vec<()>>()
+            .await?;
 
         Ok(())
     }
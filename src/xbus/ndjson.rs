@@ -0,0 +1,164 @@
+/// Newline delimited json archives of trips, gzip compressed when the path ends with .gz
+///
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use super::TripsHit;
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|x| x == "gz")
+}
+
+enum Output {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Output::Plain(writer) => writer,
+            Output::Gzip(writer) => writer,
+        }
+    }
+}
+
+/// Trips are written to a temporary file next to the path, moved into place by finish
+pub struct NdjsonWriter {
+    /// None once finished
+    output: Option<Output>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    written: usize,
+}
+
+impl NdjsonWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut tmp_name = path
+            .file_name()
+            .context("Output path has no file name")?
+            .to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let file =
+            File::create(&tmp_path).with_context(|| format!("Could not create {tmp_path:?}"))?;
+        let file = BufWriter::new(file);
+
+        let output = if is_gzip(path) {
+            Output::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            Output::Plain(file)
+        };
+
+        Ok(NdjsonWriter {
+            output: Some(output),
+            path: path.to_path_buf(),
+            tmp_path,
+            written: 0,
+        })
+    }
+
+    pub fn write(&mut self, hit: &TripsHit) -> Result<()> {
+        let writer = self.output.as_mut().unwrap().writer();
+        serde_json::to_writer(&mut *writer, hit)?;
+        writer.write_all(b"\n")?;
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Flush buffers, write the gzip trailer and move the file into place
+    pub fn finish(mut self) -> Result<()> {
+        let mut file = match self.output.take().unwrap() {
+            Output::Plain(file) => file,
+            Output::Gzip(writer) => writer.finish()?,
+        };
+        file.flush()?;
+        drop(file);
+
+        fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("Could not replace {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+impl Drop for NdjsonWriter {
+    /// Remove the temporary file of a writer dropped without finishing
+    fn drop(&mut self) {
+        if self.output.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Read trips written by `NdjsonWriter`
+pub fn read_ndjson<P: AsRef<Path>>(path: P) -> Result<impl Iterator<Item = Result<TripsHit>>> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Could not open {path:?}"))?;
+
+    let reader: Box<dyn BufRead> = if is_gzip(path) {
+        Box::new(BufReader::new(GzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    Ok(reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|x| x.is_empty()))
+        .map(|(line_i, line)| {
+            let hit = serde_json::from_str(&line?)
+                .with_context(|| format!("Could not parse trip on line {}", line_i + 1))?;
+            Ok(hit)
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HIT: &str = r#"{"snapshot_id":"s1","snapshot_timestamp":"2023-05-01T08:00:00Z","snapshot_uid":"u1","departure_time":"2023-05-01T10:00:00+02:00[Europe/Berlin]","arrival_time":"2023-05-01T11:30:00+01:00[Europe/London]","total_price":"12.50","currency":"EUR","booked_out":false,"electronic_ticket_available":null,"departure_date":"2023-05-01","departure_station":{"uid":"A"},"arrival_station":{"uid":"B"},"marketing_carrier":{"uid":"FBRA"},"departure_city":{"uid":null},"arrival_city":{"uid":null},"departure_area":{"uid":null},"arrival_area":{"uid":null},"segments":[],"fares":[]}"#;
+
+    #[test]
+    fn test_roundtrip() {
+        let hit: TripsHit = serde_json::from_str(HIT).unwrap();
+        assert_eq!(hit.arrival_time.timezone(), chrono_tz::Europe::London);
+        assert_eq!(hit.total_price.to_string(), "12.50");
+
+        let dir = std::env::temp_dir().join(format!("rdtfs-ndjson-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for name in ["trips.ndjson", "trips.ndjson.gz"] {
+            let path = dir.join(name);
+            let mut writer = NdjsonWriter::create(&path).unwrap();
+            writer.write(&hit).unwrap();
+            writer.write(&hit).unwrap();
+            writer.finish().unwrap();
+
+            let read: Vec<TripsHit> = read_ndjson(&path).unwrap().map(|x| x.unwrap()).collect();
+            assert_eq!(read.len(), 2);
+            assert_eq!(serde_json::to_string(&read[1]).unwrap(), HIT);
+        }
+
+        // Unfinished output is removed, an earlier version is kept
+        let path = dir.join("trips.ndjson");
+        let mut writer = NdjsonWriter::create(&path).unwrap();
+        writer.write(&hit).unwrap();
+        drop(writer);
+        assert_eq!(read_ndjson(&path).unwrap().count(), 2);
+        assert!(!dir.join("trips.ndjson.tmp").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+/// Typed search requests of the trips index serializing to the elasticsearch query DSL
+///
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use super::TripsFilter;
+
+/// Single field object such as {"marketing_carrier.uid": "FBRA"}
+type FieldMap<V> = BTreeMap<&'static str, V>;
+
+fn field<V>(name: &'static str, value: V) -> FieldMap<V> {
+    BTreeMap::from([(name, value)])
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DateRange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gte: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lte: Option<NaiveDate>,
+}
+
+/// Filter clause, a trip must match all clauses of a query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Clause {
+    Term(FieldMap<serde_json::Value>),
+    Terms(FieldMap<Vec<String>>),
+    Range(FieldMap<DateRange>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct BoolQuery {
+    filter: Vec<Clause>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct Query {
+    bool: BoolQuery,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct PointInTime {
+    id: String,
+    keep_alive: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SourceFilter {
+    includes: Vec<String>,
+    excludes: Vec<String>,
+}
+
+/// Search request body of the trips index
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TripsQuery {
+    query: Query,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sort: Vec<FieldMap<SortOrder>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pit: Option<PointInTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_after: Option<serde_json::Value>,
+    #[serde(rename = "_source", skip_serializing_if = "Option::is_none")]
+    source: Option<SourceFilter>,
+}
+
+impl TripsQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn clause(mut self, clause: Clause) -> Self {
+        self.query.bool.filter.push(clause);
+        self
+    }
+
+    pub fn clauses(&self) -> &[Clause] {
+        &self.query.bool.filter
+    }
+
+    pub fn carrier(self, carrier: &str) -> Self {
+        self.clause(Clause::Term(field("marketing_carrier.uid", carrier.into())))
+    }
+
+    /// Departure dates between from and to inclusive, open ended if a bound is missing
+    pub fn departure_dates(self, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Self {
+        if from.is_none() && to.is_none() {
+            return self;
+        }
+        self.clause(Clause::Range(field(
+            "departure_date",
+            DateRange { gte: from, lte: to },
+        )))
+    }
+
+    /// Trips departing from any of the stations, no restriction if empty
+    pub fn departure_stations(self, stations: &[String]) -> Self {
+        if stations.is_empty() {
+            return self;
+        }
+        self.clause(Clause::Terms(field(
+            "departure_station.uid",
+            stations.to_vec(),
+        )))
+    }
+
+    /// Trips arriving at any of the stations, no restriction if empty
+    pub fn arrival_stations(self, stations: &[String]) -> Self {
+        if stations.is_empty() {
+            return self;
+        }
+        self.clause(Clause::Terms(field(
+            "arrival_station.uid",
+            stations.to_vec(),
+        )))
+    }
+
+    pub fn booked_out(self, booked_out: bool) -> Self {
+        self.clause(Clause::Term(field("booked_out", booked_out.into())))
+    }
+
+    /// Apply all restrictions of a filter
+    pub fn filter(self, filter: &TripsFilter) -> Self {
+        let query = self
+            .departure_dates(filter.departure_date_from, filter.departure_date_to)
+            .departure_stations(&filter.departure_stations)
+            .arrival_stations(&filter.arrival_stations);
+        match filter.booked_out {
+            Some(booked_out) => query.booked_out(booked_out),
+            None => query,
+        }
+    }
+
+    /// Add a sort key, earlier keys take precedence
+    pub fn sort_by(mut self, field_name: &'static str, order: SortOrder) -> Self {
+        self.sort.push(field(field_name, order));
+        self
+    }
+
+    pub fn point_in_time(mut self, id: &str, keep_alive: &str) -> Self {
+        self.pit = Some(PointInTime {
+            id: id.to_string(),
+            keep_alive: keep_alive.to_string(),
+        });
+        self
+    }
+
+    /// Continue after the sort values of the last hit of the previous page
+    pub fn search_after(mut self, after: Option<&serde_json::Value>) -> Self {
+        self.search_after = after.cloned();
+        self
+    }
+
+    /// Restrict returned _source fields, all fields if both lists are empty
+    pub fn source(mut self, includes: &[String], excludes: &[String]) -> Self {
+        self.source = if includes.is_empty() && excludes.is_empty() {
+            None
+        } else {
+            Some(SourceFilter {
+                includes: includes.to_vec(),
+                excludes: excludes.to_vec(),
+            })
+        };
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_filter_clauses() {
+        let query = TripsQuery::new().carrier("FBRA");
+        assert_eq!(
+            query
+                .clone()
+                .filter(&TripsFilter::default())
+                .clauses()
+                .len(),
+            1
+        );
+
+        let filter = TripsFilter {
+            departure_date_from: NaiveDate::from_ymd_opt(2023, 5, 1),
+            departure_stations: vec!["DEBERZOB".to_string()],
+            booked_out: Some(false),
+            ..Default::default()
+        };
+        let clauses: Vec<_> = query
+            .filter(&filter)
+            .clauses()
+            .iter()
+            .map(|x| serde_json::to_value(x).unwrap())
+            .collect();
+
+        assert_eq!(
+            clauses[1],
+            json!({"range": {"departure_date": {"gte": "2023-05-01"}}})
+        );
+        assert_eq!(
+            clauses[2],
+            json!({"terms": {"departure_station.uid": ["DEBERZOB"]}})
+        );
+        assert_eq!(clauses[3], json!({"term": {"booked_out": false}}));
+    }
+
+    #[test]
+    fn test_query_body() {
+        let query = TripsQuery::new()
+            .carrier("FBRA")
+            .sort_by("snapshot_id", SortOrder::Asc)
+            .point_in_time("pit", "5m")
+            .search_after(Some(&json!(["s1", 7])))
+            .source(&[], &["fares".to_string()]);
+
+        assert_eq!(
+            serde_json::to_value(&query).unwrap(),
+            json!({
+                "query": {"bool": {"filter": [{"term": {"marketing_carrier.uid": "FBRA"}}]}},
+                "sort": [{"snapshot_id": "asc"}],
+                "pit": {"id": "pit", "keep_alive": "5m"},
+                "search_after": ["s1", 7],
+                "_source": {"includes": [], "excludes": ["fares"]},
+            })
+        );
+    }
+}